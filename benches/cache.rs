@@ -0,0 +1,27 @@
+//! Compares verifying credentials with and without the cache of verified credentials.
+//!
+//! Run with `cargo bench --features nightly` (requires a nightly compiler).
+
+#![feature(test)]
+
+extern crate dynonym;
+extern crate test;
+
+use dynonym::http::cache::Cache;
+use dynonym::types::Hash;
+
+use test::Bencher;
+
+#[bench]
+fn verify_uncached(b: &mut Bencher) {
+    let cache = Cache::new(0);
+    let hash: Hash = "s3cr3t".into();
+    b.iter(|| assert!(cache.verify("tobias", "s3cr3t", &hash)));
+}
+
+#[bench]
+fn verify_cached(b: &mut Bencher) {
+    let cache = Cache::new(60);
+    let hash: Hash = "s3cr3t".into();
+    b.iter(|| assert!(cache.verify("tobias", "s3cr3t", &hash)));
+}
@@ -0,0 +1,380 @@
+//! Audit log
+//!
+//! Security-relevant events, i.e. logins, denied requests, configuration changes and DNS updates,
+//! are recorded in an audit log file. Every line of the file is a JSON-encoded entry containing a
+//! sequence number, the time, the [`Event`] and two SHA-256 hashes: the hash of the previous entry
+//! and the hash of the entry itself. Thus, the entries form a chain: Editing, inserting or
+//! removing an entry breaks the chain.
+//!
+//! Since removing entries from the end of the file would not break the chain, the sequence number
+//! and the hash of the last entry are additionally kept in a separate head file (the audit log
+//! file's path with the extension `.head`). A crash right after writing an entry may leave the
+//! head one entry behind. This is tolerated.
+//!
+//! Note: The hashes are not keyed. They reveal accidental corruption and careless edits, e.g. by
+//! a user who may write the audit log file but not the head file. Anyone who may write both files
+//! can recompute the whole chain, though. In order to protect the audit log against such an
+//! attacker, e.g. against someone who took over the server, the audit log file and the head file
+//! have to be copied to a place beyond the attacker's reach regularly, e.g. another host. An older
+//! copy of the head then reveals any later change of the entries it covers.
+//!
+//! A [`Trail`] records events, [`verify`] checks a whole audit log file.
+//!
+//! [`Event`]: enum.Event.html
+//! [`Trail`]: struct.Trail.html
+//! [`verify`]: fn.verify.html
+
+use errors::*;
+use history::Effect;
+use sign::now;
+use types::{Domain, Rcode};
+
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind as IoErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str;
+
+/// A security-relevant event.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    /// A user tried to log in, either using a password or a pre-signed update URL.
+    Login {
+        user: String,
+        source: IpAddr,
+        success: bool,
+    },
+
+    /// A user was denied a request, e.g. since the client's address is not allowed.
+    Denied {
+        user: String,
+        source: IpAddr,
+        reason: String,
+    },
+
+    /// The configuration was changed using the command line.
+    Config {
+        uid: u32,
+        command: String,
+    },
+
+    /// A user tried to update a domain.
+    Update {
+        user: String,
+        source: IpAddr,
+        domain: Domain,
+        ip: IpAddr,
+        result: Effect,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rcode: Option<Rcode>,
+    },
+}
+
+/// The hash "preceding" the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// An entry without its own hash.
+#[derive(Debug, Deserialize, Serialize)]
+struct Body {
+    seq: u64,
+    time: u64,
+    prev: String,
+    event: Event,
+}
+
+/// The sequence number and the hash of the last entry.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Head {
+    seq: u64,
+    hash: String,
+}
+
+/// A writer for an audit log file.
+///
+/// A `Trail` without a path is disabled, i.e. it silently discards every event. Several processes
+/// may record events into the same file at the same time.
+#[derive(Debug)]
+pub struct Trail {
+    path: Option<PathBuf>,
+}
+
+impl Trail {
+    /// Creates a new trail writing to the audit log file at the given path, if any.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Trail { path }
+    }
+
+    /// Records the given event.
+    pub fn record(&self, event: Event) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        // Open the file and lock it
+        // Note: The lock is released as soon as the file is closed.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .chain_err(|| ErrorKind::AuditFileOpen(path.clone()))?;
+        if unsafe { ::libc::flock(file.as_raw_fd(), ::libc::LOCK_EX) } != 0 {
+            bail!(ErrorKind::AuditFileLock(path.clone()));
+        }
+
+        // Chain the new entry to the last one
+        // Note: If the head lags behind, the last entry directly follows it.
+        let mut head = read_head(path)?;
+        if let Some((last, hash)) = read_last(path)? {
+            let follows = match head {
+                Some(ref head) => last.seq == head.seq + 1 && last.prev == head.hash,
+                None => last.seq == 0 && last.prev == GENESIS,
+            };
+            if follows {
+                head = Some(Head { seq: last.seq, hash });
+            }
+        }
+        let body = Body {
+            seq: head.as_ref().map_or(0, |head| head.seq + 1),
+            time: now(),
+            prev: head.map_or(GENESIS.into(), |head| head.hash),
+            event,
+        };
+        let json = serde_json::to_string(&body)
+            .chain_err(|| ErrorKind::AuditFileWrite(path.clone()))?;
+        let hash = digest(&json);
+
+        // Write the entry, including its hash, and the new head
+        let line = format!("{},\"hash\":\"{}\"}}\n", &json[..json.len() - 1], hash);
+        file.write_all(line.as_bytes())
+            .chain_err(|| ErrorKind::AuditFileWrite(path.clone()))?;
+        write_head(path, &Head { seq: body.seq, hash })
+    }
+}
+
+/// Verifies the audit log file at the given path and returns the number of entries.
+pub fn verify(path: &Path) -> Result<u64> {
+    let file = File::open(path)
+        .chain_err(|| ErrorKind::AuditFileOpen(path.into()))?;
+
+    // Follow the chain
+    // Note: `before` is the hash preceding `prev`, in case the head lags behind.
+    let (mut seq, mut prev, mut before) = (0, GENESIS.to_string(), None);
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.chain_err(|| ErrorKind::AuditFileRead(path.into()))?;
+
+        // Check the body and its hash
+        let (body, hash) = parse(&line)
+            .chain_err(|| ErrorKind::AuditFileDecode(path.into(), i + 1))?
+            .ok_or_else(|| ErrorKind::AuditFileTampered(path.into(), i + 1))?;
+        if body.seq != seq || body.prev != prev {
+            bail!(ErrorKind::AuditFileTampered(path.into(), i + 1));
+        }
+
+        seq += 1;
+        before = Some(prev);
+        prev = hash;
+    }
+
+    // Compare the end of the chain with the head
+    let head = read_head(path)?;
+    match head {
+        None if seq == 0 => Ok(0),
+        None if seq == 1 => Ok(1),
+        Some(ref head) if head.seq + 1 == seq && head.hash == prev => Ok(seq),
+        Some(ref head) if head.seq + 2 == seq && Some(&head.hash) == before.as_ref() => Ok(seq),
+        Some(ref head) if head.seq + 1 > seq => {
+            bail!(ErrorKind::AuditFileTruncated(path.into()))
+        },
+        _ => bail!(ErrorKind::AuditFileTampered(path.into(), seq as usize)),
+    }
+}
+
+/// Splits the given line into the body of the entry and its hash. If the line is not a
+/// well-formed entry or the hash does not match the body, `None` is returned.
+fn parse(line: &str) -> ::std::result::Result<Option<(Body, String)>, serde_json::Error> {
+    let split = match line.rfind(",\"hash\":\"") {
+        Some(split) if line.ends_with("\"}") => split,
+        _ => return Ok(None),
+    };
+    let json = format!("{}}}", &line[..split]);
+    let hash = &line[split + 9..line.len() - 2];
+    if digest(&json) != hash {
+        return Ok(None);
+    }
+    let body = serde_json::from_str(&json)?;
+    Ok(Some((body, hash.into())))
+}
+
+/// Reads the last entry of the audit log file at the given path, including its hash, if it is
+/// well-formed.
+///
+/// The file is read backwards, starting with its last 4 KiB.
+fn read_last(path: &Path) -> Result<Option<(Body, String)>> {
+    let mut file = File::open(path)
+        .chain_err(|| ErrorKind::AuditFileOpen(path.into()))?;
+    let len = file.seek(SeekFrom::End(0))
+        .chain_err(|| ErrorKind::AuditFileRead(path.into()))?;
+
+    let mut size = len.min(4096);
+    loop {
+        let mut buf = vec![0; size as usize];
+        file.seek(SeekFrom::Start(len - size))
+            .and_then(|_| file.read_exact(&mut buf))
+            .chain_err(|| ErrorKind::AuditFileRead(path.into()))?;
+
+        // Find the beginning of the last line, ignoring trailing newlines
+        let end = buf.len() - buf.iter().rev().take_while(|&&byte| byte == b'\n').count();
+        let line = match buf[..end].iter().rposition(|&byte| byte == b'\n') {
+            Some(i) => &buf[i + 1..end],
+            None if size == len => &buf[..end],
+            None => {
+                size = len.min(size * 2);
+                continue;
+            },
+        };
+        return match str::from_utf8(line).map(parse) {
+            Ok(Ok(entry)) => Ok(entry),
+            _ => Ok(None),
+        };
+    }
+}
+
+/// Computes the hex-encoded SHA-256 hash of the given string.
+fn digest(s: &str) -> String {
+    format!("{:x}", Sha256::digest(s.as_bytes()))
+}
+
+/// Returns the path of the head file that belongs to the given audit log file.
+fn head_path(path: &Path) -> PathBuf {
+    let mut head = path.as_os_str().to_owned();
+    head.push(".head");
+    head.into()
+}
+
+/// Reads the head file that belongs to the given audit log file. A missing head file means that
+/// there is no entry yet.
+fn read_head(path: &Path) -> Result<Option<Head>> {
+    let path = head_path(path);
+    let mut buf = String::new();
+    match File::open(&path) {
+        Ok(mut file) => file.read_to_string(&mut buf)
+            .chain_err(|| ErrorKind::AuditFileRead(path.clone()))?,
+        Err(ref err) if err.kind() == IoErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).chain_err(|| ErrorKind::AuditFileOpen(path.clone())),
+    };
+    let head = serde_json::from_str(&buf)
+        .chain_err(|| ErrorKind::AuditFileDecode(path.clone(), 1))?;
+    Ok(Some(head))
+}
+
+/// Writes the head file that belongs to the given audit log file (atomically).
+fn write_head(path: &Path, head: &Head) -> Result<()> {
+    let path = head_path(path);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let json = serde_json::to_string(head)
+        .chain_err(|| ErrorKind::AuditFileWrite(path.clone()))?;
+    File::create(&tmp)
+        .and_then(|mut file| file.write_all(json.as_bytes()))
+        .and_then(|_| fs::rename(&tmp, &path))
+        .chain_err(|| ErrorKind::AuditFileWrite(path.clone()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn login(user: &str) -> Event {
+        Event::Login {
+            user: user.into(),
+            source: "192.0.2.1".parse().unwrap(),
+            success: true,
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        let mut buf = String::new();
+        File::open(path).unwrap().read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    fn write(path: &Path, buf: &str) {
+        File::create(path).unwrap().write_all(buf.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn audit_verify() {
+        let file = NamedTempFile::new().unwrap();
+        let trail = Trail::new(Some(file.path().into()));
+        trail.record(login("tobias")).unwrap();
+        trail.record(Event::Config { uid: 1000, command: "configure users rm tobias".into() })
+            .unwrap();
+        trail.record(login("sebastian")).unwrap();
+        assert_eq!(verify(file.path()).unwrap(), 3);
+        fs::remove_file(head_path(file.path())).unwrap();
+    }
+
+    #[test]
+    fn audit_disabled() {
+        assert!(Trail::new(None).record(login("tobias")).is_ok());
+    }
+
+    #[test]
+    fn audit_edited() {
+        let file = NamedTempFile::new().unwrap();
+        let trail = Trail::new(Some(file.path().into()));
+        trail.record(login("tobias")).unwrap();
+        trail.record(login("sebastian")).unwrap();
+
+        let buf = read(file.path()).replacen("tobias", "mallory", 1);
+        write(file.path(), &buf);
+        match *verify(file.path()).unwrap_err().kind() {
+            ErrorKind::AuditFileTampered(_, 1) => (),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+        fs::remove_file(head_path(file.path())).unwrap();
+    }
+
+    #[test]
+    fn audit_truncated() {
+        let file = NamedTempFile::new().unwrap();
+        let trail = Trail::new(Some(file.path().into()));
+        trail.record(login("tobias")).unwrap();
+        let buf = read(file.path());
+        trail.record(login("sebastian")).unwrap();
+
+        write(file.path(), &buf);
+        match *verify(file.path()).unwrap_err().kind() {
+            ErrorKind::AuditFileTruncated(_) => (),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+        fs::remove_file(head_path(file.path())).unwrap();
+    }
+
+    #[test]
+    fn audit_head_lagging() {
+        let file = NamedTempFile::new().unwrap();
+        let trail = Trail::new(Some(file.path().into()));
+        trail.record(login("tobias")).unwrap();
+        let head = read(&head_path(file.path()));
+
+        // A crash right after writing the entry leaves the old head behind
+        trail.record(login("sebastian")).unwrap();
+        write(&head_path(file.path()), &head);
+        assert_eq!(verify(file.path()).unwrap(), 2);
+
+        // The next entry follows the last one, not the head
+        trail.record(login("tobias")).unwrap();
+        assert_eq!(verify(file.path()).unwrap(), 3);
+        fs::remove_file(head_path(file.path())).unwrap();
+    }
+}
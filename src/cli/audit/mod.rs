@@ -0,0 +1,22 @@
+pub mod verify;
+
+use errors::*;
+
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("audit")
+        .about("Works with the audit log")
+
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+
+        .subcommand(verify::setup())
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Match and execute a subcommand
+    match args.subcommand() {
+        ("verify", Some(args)) => verify::call(args),
+        _                      => unreachable!(),
+    }
+}
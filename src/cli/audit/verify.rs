@@ -0,0 +1,38 @@
+use audit;
+use config::Config;
+use errors::*;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::path::PathBuf;
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("verify")
+        .about("Detects edits and truncation of the audit log")
+
+        .arg(
+            Arg::with_name("FILE")
+                .help("Specifies the audit log file [default: taken from the config]")
+                .index(1)
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Determine the audit log file
+    let file = match args.value_of("FILE") {
+        Some(file) => PathBuf::from(file),
+        None => {
+            let config = Config::load(args.value_of("config").unwrap())?;
+            match config.audit.file {
+                Some(file) => file,
+                None => bail!("There is no audit log file configured"),
+                // TODO Use proper error!
+            }
+        },
+    };
+
+    // Verify
+    let entries = audit::verify(&file)?;
+    println!("The audit log '{}' is intact ({} entries)", file.display(), entries);
+
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use config::Config;
+use errors::*;
+
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("audit")
+        .about("Makes changes to the audit log configuration")
+
+        .arg(
+            Arg::with_name("file")
+                .long("file")
+                .value_name("FILE")
+                .help("Changes the audit log file")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("off")
+                .long("off")
+                .help("Stops writing an audit log")
+        )
+        .group(
+            ArgGroup::with_name("change")
+                .args(&["file", "off"])
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Load the config
+    let config_file = args.value_of("config").unwrap();
+    let mut config = Config::load(config_file)?;
+
+    // Change the file, if requested
+    if let Some(file) = args.value_of("file") {
+        config.audit.file = Some(file.into());
+    }
+    if args.is_present("off") {
+        config.audit.file = None;
+    }
+
+    // Store the config
+    config.store(config_file)?;
+
+    Ok(())
+}
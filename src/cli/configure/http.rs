@@ -18,7 +18,43 @@ pub fn setup<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("workers")
                 .long("workers")
                 .value_name("NUMBER")
-                .help("Changes the number of worker threads")
+                .help("Changes the number of threads verifying passwords")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .long("metrics")
+                .value_name("SWITCH")
+                .help("Turns the metrics endpoint on or off")
+                .possible_values(&["on", "off"])
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("metrics-socket")
+                .long("metrics-socket")
+                .value_name("ADDR")
+                .help("Changes the socket address the metrics endpoint is served at")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .value_name("USER")
+                .help("Changes the user the server runs as after binding its sockets")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("group")
+                .long("group")
+                .value_name("GROUP")
+                .help("Changes the group the server runs as after binding its sockets")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("chroot")
+                .long("chroot")
+                .value_name("DIR")
+                .help("Changes the root directory of the server after binding its sockets")
                 .takes_value(true)
         )
 }
@@ -40,6 +76,26 @@ pub fn call(args: &ArgMatches) -> Result<()> {
         config.http.workers = workers;
     }
 
+    // Change the metrics endpoint, if requested
+    if let Some(switch) = args.value_of("metrics") {
+        config.http.metrics.enabled = switch == "on";
+    }
+    if let Some(socket_str) = args.value_of("metrics-socket") {
+        let socket = socket_str.parse()?;   // TODO Chain the error!
+        config.http.metrics.socket = socket;
+    }
+
+    // Change the privileges, if requested
+    if let Some(user) = args.value_of("user") {
+        config.http.user = Some(user.into());
+    }
+    if let Some(group) = args.value_of("group") {
+        config.http.group = Some(group.into());
+    }
+    if let Some(dir) = args.value_of("chroot") {
+        config.http.chroot = Some(dir.into());
+    }
+
     // Store the config
     config.store(config_file)?;
 
@@ -1,11 +1,15 @@
+pub mod audit;
 pub mod default;
 pub mod dns;
 pub mod http;
 pub mod users;
 
+use audit::{Event, Trail};
+use config::Config;
 use errors::*;
 
 use clap::{App, AppSettings, ArgMatches, SubCommand};
+use std::env;
 
 pub fn setup<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("configure")
@@ -13,6 +17,7 @@ pub fn setup<'a, 'b>() -> App<'a, 'b> {
 
         .setting(AppSettings::SubcommandRequiredElseHelp)
 
+        .subcommand(  audit::setup())
         .subcommand(default::setup())
         .subcommand(    dns::setup())
         .subcommand(   http::setup())
@@ -20,12 +25,33 @@ pub fn setup<'a, 'b>() -> App<'a, 'b> {
 }
 
 pub fn call(args: &ArgMatches) -> Result<()> {
+    // Remember the audit log file before the change, e.g. in case the audit log is turned off
+    // Note: There is no audit log file unless the config file exists.
+    let config_file = args.value_of("config").unwrap();
+    let before = Config::load(config_file).ok().and_then(|config| config.audit.file);
+
     // Match and execute a subcommand
     match args.subcommand() {
+        ("audit"  , Some(args)) =>   audit::call(args),
         ("default", Some(args)) => default::call(args),
         ("dns"    , Some(args)) =>     dns::call(args),
         ("http"   , Some(args)) =>    http::call(args),
         ("users"  , Some(args)) =>   users::call(args),
         _                       =>      unreachable!(),
+    }?;
+
+    // Record the change in the audit log, both before and after the change
+    let after = Config::load(config_file)?.audit.file;
+    let files = match (before, after) {
+        (Some(before), Some(after)) if before != after => vec![before, after],
+        (before, after) => before.or(after).into_iter().collect(),
+    };
+    let event = Event::Config {
+        uid: unsafe { ::libc::getuid() },
+        command: env::args().skip(1).collect::<Vec<_>>().join(" "),
+    };
+    for file in files {
+        Trail::new(Some(file)).record(event.clone())?;
     }
+    Ok(())
 }
@@ -1,5 +1,6 @@
-use config::Config;
+use config::{Config, User};
 use errors::*;
+use types::Hash;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 
@@ -30,7 +31,8 @@ pub fn call(args: &ArgMatches) -> Result<()> {
     let pw = ::rpassword::prompt_password_stdout("Please enter a password: ")?;
 
     // Create the user
-    config.users.add(user, &pw);
+    let pw = Hash::new(&pw, &config.hashing)?;
+    config.users.insert(user.into(), User::with_hash(pw));
 
     // Store the config
     config.store(config_file)?;
@@ -0,0 +1,57 @@
+use config::Config;
+use errors::*;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("add")
+        .about("Allows a user to send requests from an address block")
+
+        .arg(
+            Arg::with_name("USER")
+                .help("Specifies the user")
+                .required(true)
+                .index(1)
+        )
+        .arg(
+            Arg::with_name("CIDR")
+                .help("Specifies the address block, e.g. 192.0.2.0/24")
+                .required(true)
+                .index(2)
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Load the config
+    let config_file = args.value_of("config").unwrap();
+    let mut config = Config::load(config_file)?;
+
+    {
+        // Find the user
+        let user_str = args.value_of("USER").unwrap();
+        let user = match config.user_mut(user_str) {
+            Some(user) => user,
+            None => bail!(format!("A user named '{}' does not exist", user_str)),
+            // TODO Use proper error!
+        };
+
+        // Decode the address block
+        let cidr_str = args.value_of("CIDR").unwrap();
+        let cidr = cidr_str.parse()?;
+
+        // Allow the address block
+        if !user.clients.add(cidr) {
+            bail!(format!(
+                "The user '{}' is already allowed to send requests from '{}'",
+                user_str,
+                cidr_str,
+            ));
+            // TODO Use proper error!
+        }
+    }
+
+    // Store the config
+    config.store(config_file)?;
+
+    Ok(())
+}
@@ -1,7 +1,10 @@
 pub mod add;
 pub mod auth;
+pub mod clients;
+pub mod public_only;
 pub mod pw;
 pub mod rm;
+pub mod targets;
 
 use errors::*;
 
@@ -13,19 +16,25 @@ pub fn setup<'a, 'b>() -> App<'a, 'b> {
 
         .setting(AppSettings::SubcommandRequiredElseHelp)
 
-        .subcommand( add::setup())
-        .subcommand(auth::setup())
-        .subcommand(  pw::setup())
-        .subcommand(  rm::setup())
+        .subcommand(        add::setup())
+        .subcommand(       auth::setup())
+        .subcommand(    clients::setup())
+        .subcommand(public_only::setup())
+        .subcommand(         pw::setup())
+        .subcommand(         rm::setup())
+        .subcommand(    targets::setup())
 }
 
 pub fn call(args: &ArgMatches) -> Result<()> {
     // Match and execute a subcommand
     match args.subcommand() {
-        ("add" , Some(args)) =>  add::call(args),
-        ("auth", Some(args)) => auth::call(args),
-        ("pw"  , Some(args)) =>   pw::call(args),
-        ("rm"  , Some(args)) =>   rm::call(args),
-        _                    =>   unreachable!(),
+        ("add"        , Some(args)) =>         add::call(args),
+        ("auth"       , Some(args)) =>        auth::call(args),
+        ("clients"    , Some(args)) =>     clients::call(args),
+        ("public-only", Some(args)) => public_only::call(args),
+        ("pw"         , Some(args)) =>          pw::call(args),
+        ("rm"         , Some(args)) =>          rm::call(args),
+        ("targets"    , Some(args)) =>     targets::call(args),
+        _                           =>          unreachable!(),
     }
 }
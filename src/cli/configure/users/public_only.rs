@@ -0,0 +1,44 @@
+use config::Config;
+use errors::*;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("public-only")
+        .about("Restricts a user to point domains to public addresses only")
+
+        .arg(
+            Arg::with_name("USER")
+                .help("Specifies the user")
+                .required(true)
+                .index(1)
+        )
+        .arg(
+            Arg::with_name("SWITCH")
+                .help("Turns the restriction on or off")
+                .possible_values(&["on", "off"])
+                .required(true)
+                .index(2)
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Load the config
+    let config_file = args.value_of("config").unwrap();
+    let mut config = Config::load(config_file)?;
+
+    // Find the user
+    let user = args.value_of("USER").unwrap();
+    match config.user_mut(user) {
+        Some(user) => {
+            // Change the restriction
+            user.public_only = args.value_of("SWITCH").unwrap() == "on";
+        },
+        None => bail!(format!("A user named '{}' does not exist", user)), // TODO Use proper error!
+    }
+
+    // Store the config
+    config.store(config_file)?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use config::Config;
 use errors::*;
+use types::Hash;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
 
@@ -22,13 +23,14 @@ pub fn call(args: &ArgMatches) -> Result<()> {
 
     // Find the user
     let user = args.value_of("USER").unwrap();
+    let hashing = config.hashing;
     match config.user_mut(user) {
         Some(user) => {
             // Prompt for a password
             let pw = ::rpassword::prompt_password_stdout("Please enter a password: ")?;
 
             // Change the password
-            user.pw = pw.as_str().into();
+            user.pw = Hash::new(&pw, &hashing)?;
         },
         None => bail!(format!("A user named '{}' does not exist", user)), // TODO Use proper error!
     }
@@ -0,0 +1,25 @@
+pub mod add;
+pub mod rm;
+
+use errors::*;
+
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("targets")
+        .about("Manages the address blocks a user may point domains to")
+
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+
+        .subcommand(add::setup())
+        .subcommand( rm::setup())
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Match and execute a subcommand
+    match args.subcommand() {
+        ("add", Some(args)) => add::call(args),
+        ("rm" , Some(args)) =>  rm::call(args),
+        _                   => unreachable!(),
+    }
+}
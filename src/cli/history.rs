@@ -0,0 +1,82 @@
+use errors::*;
+use history::{Effect, Filter, History};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::path::Path;
+use yansi::Paint;
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("history")
+        .about("Shows the update history")
+
+        .arg(
+            Arg::with_name("domain")
+                .long("domain")
+                .value_name("DOMAIN")
+                .help("Only shows updates of the given domain")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .value_name("USER")
+                .help("Only shows updates requested by the given user")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .value_name("TIME")
+                .help("Only shows updates since the given time (in seconds since the Unix epoch)")
+                .takes_value(true)
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Assemble the filter
+    let filter = Filter {
+        domain: match args.value_of("domain") {
+            Some(domain_str) => Some(domain_str.parse()?),
+            None => None,
+        },
+        user: args.value_of("user").map(Into::into),
+        since: match args.value_of("since") {
+            Some(since_str) => Some(since_str.parse()?),  // TODO Chain the error!
+            None => None,
+        },
+    };
+
+    // Read the history
+    let history_file = args.value_of("history").unwrap();
+    let entries = History::read(Path::new(history_file))?;
+
+    // Print the matching entries and warn about malformed lines
+    for entry in entries {
+        let entry = match entry {
+            Ok(ref entry) if !filter.matches(entry) => continue,
+            Ok(entry) => entry,
+            Err(err) => {
+                eprintln!("{} {}", Paint::yellow("warning:").bold(), err);
+                continue;
+            },
+        };
+        let result = match (entry.result, entry.rcode) {
+            (Effect::Changed, _) => "changed".to_string(),
+            (Effect::Unchanged, _) => "unchanged".to_string(),
+            (Effect::Failed, Some(rcode)) => format!("failed ({})", rcode),
+            (Effect::Failed, None) => "failed".to_string(),
+            (Effect::Rejected, _) => "rejected".to_string(),
+        };
+        println!(
+            "{} {} {} {} {} {}",
+            entry.time,
+            entry.user,
+            entry.source,
+            entry.domain,
+            entry.ip,
+            result,
+        );
+    }
+
+    Ok(())
+}
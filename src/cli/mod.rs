@@ -1,7 +1,13 @@
 //! Command-line argument parsing and instruction assembly
 
+pub mod audit;
 pub mod configure;
+pub mod history;
+pub mod reload;
 pub mod serve;
+pub mod sign;
+pub mod status;
+pub mod stop;
 
 use errors::*;
 
@@ -46,16 +52,51 @@ pub fn setup<'a, 'b>() -> App<'a, 'b> {
                 )
                 .global(true)
         )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .value_name("FILE")
+                .help("Sets a custom history file")
+                .takes_value(true)
+                .default_value(
+                    concat!(crate_name!(), ".history")
+                )
+                .global(true)
+        )
+        .arg(
+            Arg::with_name("lock")
+                .short("l")
+                .long("lock")
+                .value_name("FILE")
+                .help("Sets a custom lock file")
+                .takes_value(true)
+                .default_value(
+                    concat!(crate_name!(), ".lock")
+                )
+                .global(true)
+        )
 
+        .subcommand(    audit::setup())
         .subcommand(configure::setup())
+        .subcommand(  history::setup())
+        .subcommand(   reload::setup())
         .subcommand(    serve::setup())
+        .subcommand(     sign::setup())
+        .subcommand(   status::setup())
+        .subcommand(     stop::setup())
 }
 
 pub fn call(args: &ArgMatches) -> Result<()> {
     // Match and execute a subcommand
     match args.subcommand() {
+        ("audit",     Some(args)) =>     audit::call(args),
         ("configure", Some(args)) => configure::call(args),
+        ("history",   Some(args)) =>   history::call(args),
+        ("reload",    Some(args)) =>    reload::call(args),
         ("serve",     Some(args)) =>     serve::call(args),
+        ("sign",      Some(args)) =>      sign::call(args),
+        ("status",    Some(args)) =>    status::call(args),
+        ("stop",      Some(args)) =>      stop::call(args),
         _                         =>        unreachable!(),
     }
 }
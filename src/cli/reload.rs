@@ -0,0 +1,23 @@
+use errors::*;
+use lock::Lock;
+
+use clap::{App, ArgMatches, SubCommand};
+use libc;
+use std::path::Path;
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("reload")
+        .about("Makes the server reload its config")
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Find the running server
+    let lock_file = args.value_of("lock").unwrap();
+    let owner = Lock::holder(Path::new(lock_file))?;
+
+    // Ask the server to reload
+    owner.signal(libc::SIGHUP)?;
+    println!("Asked process {} to reload its config", owner.pid);
+
+    Ok(())
+}
@@ -1,43 +1,87 @@
-use config::Config;
+use config::{Config, Listener};
 use errors::*;
+use history::History;
 use lock::Lock;
 
 use clap::{App, Arg, ArgMatches, SubCommand};
+use std::env;
+use std::path::{Path, PathBuf};
 
 pub fn setup<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("serve")
         .about("Starts the server")
 
         .arg(
-            Arg::with_name("lock")
-                .short("l")
-                .long("lock")
-                .value_name("FILE")
-                .help("Sets a custom lock file")
-                .takes_value(true)
-                .default_value(
-                    concat!(crate_name!(), ".lock")
-                )
+            Arg::with_name("daemon")
+                .short("d")
+                .long("daemon")
+                .help("Runs the server in the background")
         )
 }
 
 pub fn call(args: &ArgMatches) -> Result<()> {
-    // Create a lock
-    // Note: The lock is auto-released when _lock goes out of scope!
-    let _lock = Lock::create(
-        args
-            .value_of("lock")
-            .unwrap()
-            .into()
-    )?.handle_sigint()?;
+    // Determine the paths
+    // Note: A daemon changes its working directory, so that relative paths must be resolved.
+    let daemon = args.is_present("daemon");
+    let path = |name| -> Result<PathBuf> {
+        let path = Path::new(args.value_of(name).unwrap());
+        if daemon {
+            Ok(env::current_dir()?.join(path))
+        } else {
+            Ok(path.into())
+        }
+    };
+    let config_file = path("config")?;
+    let lock_file = path("lock")?;
+    let history_file = path("history")?;
 
     // Load config
-    let config = Config::load(
-        args
-            .value_of("config")
-            .unwrap()
-    )?;
+    let config = Config::load(&config_file)?;
+    config.validate()?;
+
+    // Set up logging
+    ::logging::init(&config.http.log)?;
+
+    // Turn into a daemon, if requested
+    if daemon {
+        let listeners = config.http.listeners.iter().filter_map(|listener| match *listener {
+            Listener::Unix { ref path, .. } => Some(path),
+            _ => None,
+        });
+        let paths = config
+            .audit
+            .file
+            .iter()
+            .chain(config.http.chroot.iter())
+            .chain(config.http.log.file.iter())
+            .chain(listeners);
+        for path in paths {
+            if path.is_relative() {
+                bail!(ErrorKind::ConfigInvalid(format!(
+                    "The path '{}' must be absolute in order to run as a daemon",
+                    path.display(),
+                )));
+            }
+        }
+        ::daemon::daemonize(config.http.log.file.as_ref().map(PathBuf::as_path))?;
+    }
+
+    // Start the server, telling the original process if that fails
+    let result = start(config, config_file, lock_file, history_file);
+    if let Err(ref err) = result {
+        ::daemon::fail(err);
+    }
+    result
+}
+
+fn start(config: Config, config_file: PathBuf, lock: PathBuf, history: PathBuf) -> Result<()> {
+    // Create a lock
+    // Note: The server releases the lock when shutting down.
+    let lock = Lock::create(lock)?;
+
+    // Open the history
+    let history = History::open(history)?;
 
     // Start the server
-    ::http::serve(config)
+    ::http::serve(config, config_file, history, lock)
 }
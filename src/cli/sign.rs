@@ -0,0 +1,125 @@
+use config::Config;
+use errors::*;
+use sign::{now, Signed};
+use types::Secret;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("sign")
+        .about("Generates a pre-signed update URL")
+
+        .arg(
+            Arg::with_name("USER")
+                .help("Specifies the user")
+                .required(true)
+                .index(1)
+        )
+        .arg(
+            Arg::with_name("DOMAIN")
+                .help("Specifies the domain name")
+                .required(true)
+                .index(2)
+        )
+        .arg(
+            Arg::with_name("ipv4")
+                .long("ipv4")
+                .value_name("ADDR")
+                .help("Fixes the IPv4 address")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("ipv6")
+                .long("ipv6")
+                .value_name("ADDR")
+                .help("Fixes the IPv6 address")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("valid")
+                .long("valid")
+                .value_name("SECONDS")
+                .help("Sets the period of validity")
+                .takes_value(true)
+                .default_value("86400")
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .value_name("URL")
+                .help("Sets the base URL of the server [default: derived from the HTTP socket]")
+                .takes_value(true)
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Load the config
+    let config_file = args.value_of("config").unwrap();
+    let mut config = Config::load(config_file)?;
+
+    // Determine the base URL
+    let base = match args.value_of("url") {
+        Some(url) => url.to_owned(),
+        None => format!("http://{}", config.http.socket),
+    };
+
+    let url = {
+        // Find the user
+        let user_str = args.value_of("USER").unwrap();
+        let user = match config.user_mut(user_str) {
+            Some(user) => user,
+            None => bail!(format!("A user named '{}' does not exist", user_str)),
+            // TODO Use proper error!
+        };
+
+        // Decode the domain and check the authorization
+        let domain_str = args.value_of("DOMAIN").unwrap();
+        let domain = domain_str.parse()?;
+        if !user.domains.contains(&domain) {
+            bail!(format!(
+                "The user '{}' is not authorized for the domain '{}'",
+                user_str,
+                domain_str,
+            ));
+            // TODO Use proper error!
+        }
+
+        // Decode the IP addresses, if given
+        let ipv4 = match args.value_of("ipv4") {
+            Some(ipv4_str) => Some(ipv4_str.parse()?),  // TODO Chain the error!
+            None => None,
+        };
+        let ipv6 = match args.value_of("ipv6") {
+            Some(ipv6_str) => Some(ipv6_str.parse()?),  // TODO Chain the error!
+            None => None,
+        };
+
+        // Compute the expiry time
+        let valid: u64 = args.value_of("valid").unwrap().parse()?; // TODO Chain the error!
+        let expires = now().checked_add(valid).ok_or(ErrorKind::SignValidity(valid))?;
+
+        // Generate a secret, if necessary
+        if user.secret.is_none() {
+            user.secret = Some(Secret::generate()?);
+        }
+        let secret = user.secret.as_ref().unwrap();
+
+        // Sign
+        let signed = Signed {
+            user: user_str.into(),
+            domain,
+            ipv4,
+            ipv6,
+            expires,
+        };
+        signed.url(&base, secret)
+    };
+
+    // Store the config (since a secret may have been generated)
+    config.store(config_file)?;
+
+    // Print the URL
+    println!("{}", url);
+
+    Ok(())
+}
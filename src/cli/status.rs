@@ -0,0 +1,52 @@
+use errors::*;
+use lock::Lock;
+use sign::now;
+
+use clap::{App, ArgMatches, SubCommand};
+use std::path::Path;
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("status")
+        .about("Shows whether the server is running")
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    // Find the running server
+    let lock_file = args.value_of("lock").unwrap();
+    let owner = Lock::holder(Path::new(lock_file))?;
+
+    // Print the status
+    println!("Running as process {}", owner.pid);
+    if let Some(started) = owner.started {
+        println!("Up for {}", uptime(now().saturating_sub(started)));
+    }
+    for socket in &owner.sockets {
+        println!("Listening on {}", socket);
+    }
+
+    Ok(())
+}
+
+/// Formats the given number of seconds, e.g. `1d 2h 3m 4s`.
+fn uptime(secs: u64) -> String {
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m {}s", mins, secs),
+        (0, _, _) => format!("{}h {}m {}s", hours, mins, secs),
+        _ => format!("{}d {}h {}m {}s", days, hours, mins, secs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uptime_format() {
+        assert_eq!(uptime(4), "4s");
+        assert_eq!(uptime(3 * 60 + 4), "3m 4s");
+        assert_eq!(uptime(2 * 3600 + 4), "2h 0m 4s");
+        assert_eq!(uptime(86400 + 2 * 3600 + 3 * 60 + 4), "1d 2h 3m 4s");
+    }
+}
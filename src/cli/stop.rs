@@ -0,0 +1,60 @@
+use errors::*;
+use lock::Lock;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use libc;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The interval (in milliseconds) at which the lock file is checked.
+const INTERVAL: u64 = 100;
+
+pub fn setup<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("stop")
+        .about("Stops the server gracefully")
+
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Sets how long to wait for the server to stop")
+                .takes_value(true)
+                .default_value("30")
+        )
+}
+
+pub fn call(args: &ArgMatches) -> Result<()> {
+    let timeout: u64 = args.value_of("timeout").unwrap().parse()?;
+
+    // Find the running server
+    let lock_file = Path::new(args.value_of("lock").unwrap());
+    let owner = Lock::holder(lock_file)?;
+
+    // Ask the server to stop
+    owner.signal(libc::SIGTERM)?;
+
+    // Wait until the server released the lock
+    // Note: Any error other than an unheld lock file, e.g. a lock file that cannot be read, leaves
+    // open whether the server stopped.
+    let start = Instant::now();
+    loop {
+        match Lock::holder(lock_file) {
+            Ok(ref holder) if holder.pid == owner.pid => {},
+            Ok(_) => break,
+            Err(err) => {
+                if let ErrorKind::LockFileUnheld(_) = *err.kind() {
+                    break;
+                }
+                return Err(err);
+            },
+        }
+        if start.elapsed() >= Duration::from_secs(timeout) {
+            bail!(ErrorKind::ProcessStopTimeout(owner.pid, timeout));
+        }
+        thread::sleep(Duration::from_millis(INTERVAL));
+    }
+    println!("Stopped process {}", owner.pid);
+
+    Ok(())
+}
@@ -25,11 +25,11 @@
 //!
 //! ## Change basic parameters
 //! ```
-//! # use dynonym::config::Config;
+//! # use dynonym::config::{Config, LogLevel};
 //! # let mut config = Config::default();
 //! config.http.socket = "127.0.0.1:8053".parse().unwrap();
 //! config.http.workers = 4;
-//! # // TODO config.http.log_level = ();
+//! config.http.log.level = LogLevel::Debug;
 //!
 //! config.dns.socket = "127.0.0.1:53".parse().unwrap();
 //! config.dns.ttl = 60 /*sec*/;
@@ -73,14 +73,19 @@
 //! ```
 
 use errors::*;
-use types::{Domain, Hash};
+use types::{Algorithm, Cidr, Domain, Hash, Secret};
 
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io;
 use std::io::prelude::*;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use toml;
 
 /// An entire configuration for `dynonym` held in memory.
@@ -96,6 +101,14 @@ pub struct Config {
     /// The DNS update client configuration.
     pub dns: Dns,
 
+    /// The algorithm used to hash passwords.
+    #[serde(default)]
+    pub hashing: Algorithm,
+
+    /// The audit log configuration.
+    #[serde(default)]
+    pub audit: Audit,
+
     /// The "set" of authorized users.
     pub users: Users,
 }
@@ -121,19 +134,33 @@ impl Config {
 
     /// Stores a configuration into a file given a path.
     ///
-    /// This method will truncate an existing file without asking!
+    /// This method will replace an existing file without asking! The configuration is written
+    /// into a temporary file next to the given one first, which then replaces the given file
+    /// atomically. The permissions and the owner of an existing file are kept.
     pub fn store<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        // Open the config file write-only, truncate it if it exists
-        let mut file = File::create(&path)
-            .chain_err(|| ErrorKind::ConfigFileCreate(path.as_ref().to_owned()))?;
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
 
         // Encode configuration
         let buf = toml::to_string(&self)
-            .chain_err(|| ErrorKind::ConfigFileEncode(path.as_ref().to_owned()))?;
+            .chain_err(|| ErrorKind::ConfigFileEncode(path.to_owned()))?;
 
-        // Write the config file
-        file.write_all(buf.as_bytes())
-            .chain_err(|| ErrorKind::ConfigFileWrite(path.as_ref().to_owned()))?;
+        // Open the temporary file write-only, truncate it if it exists
+        let mut file = File::create(&tmp)
+            .chain_err(|| ErrorKind::ConfigFileCreate(tmp.clone()))?;
+
+        // Write the temporary file and replace the config file
+        // Note: The temporary file must not be left behind if anything goes wrong.
+        let replaced = keep_metadata(&file, path)
+            .and_then(|_| file.write_all(buf.as_bytes()))
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&tmp, path));
+        if let Err(err) = replaced {
+            let _ = fs::remove_file(&tmp);
+            return Err(err).chain_err(|| ErrorKind::ConfigFileWrite(path.to_owned()));
+        }
 
         Ok(())
     }
@@ -147,6 +174,65 @@ impl Config {
     pub fn user_mut(&mut self, user: &str) -> Option<&mut User> {
         self.users.get_mut(user)
     }
+
+    /// Checks the configuration for inconsistencies that decoding does not reveal.
+    pub fn validate(&self) -> Result<()> {
+        if self.http.log.output == LogOutput::File && self.http.log.file.is_none() {
+            bail!(ErrorKind::ConfigInvalid("The log output 'file' requires a log file".into()));
+        }
+        // Note: Root can escape the new root directory.
+        if self.http.chroot.is_some() && self.http.user.is_none() {
+            bail!(ErrorKind::ConfigInvalid(
+                "Changing the root directory requires switching to another user".into()
+            ));
+        }
+        let metrics = &self.http.metrics;
+        if metrics.enabled && metrics.socket == self.http.socket {
+            bail!(ErrorKind::ConfigInvalid(
+                "The metrics socket address must differ from the HTTP socket address".into()
+            ));
+        }
+        let mut sockets = vec![self.http.socket];
+        if metrics.enabled {
+            sockets.push(metrics.socket);
+        }
+        for listener in &self.http.listeners {
+            match *listener {
+                Listener::Tcp { socket } if sockets.contains(&socket) => {
+                    bail!(ErrorKind::ConfigInvalid(
+                        format!("The socket address '{}' is used twice", socket)
+                    ));
+                },
+                Listener::Tcp { socket } => sockets.push(socket),
+                Listener::Unix { .. } => { listener.mode()?; },
+            }
+        }
+        match self.hashing {
+            Algorithm::Bcrypt { cost } if cost < 4 || cost > 31 => {
+                bail!(ErrorKind::ConfigInvalid(
+                    format!("The bcrypt cost {} is not within 4 and 31", cost)
+                ));
+            },
+            Algorithm::Argon2id { lanes, .. } if lanes == 0 || lanes > 0xff_ffff => {
+                bail!(ErrorKind::ConfigInvalid(
+                    format!("The number of Argon2id lanes {} is not within 1 and 16777215", lanes)
+                ));
+            },
+            Algorithm::Argon2id { memory, lanes, .. } if memory < 8 * lanes => {
+                bail!(ErrorKind::ConfigInvalid(
+                    format!("The Argon2id memory of {} KiB is less than 8 KiB per lane", memory)
+                ));
+            },
+            Algorithm::Argon2id { iterations: 0, .. } => {
+                bail!(ErrorKind::ConfigInvalid("The number of Argon2id iterations is 0".into()));
+            },
+            _ => {},
+        }
+        if self.dns.ttl > i32::max_value() as u32 {
+            bail!(ErrorKind::ConfigInvalid(format!("The TTL {} is too large", self.dns.ttl)));
+        }
+        Ok(())
+    }
 }
 
 impl Default for Config {
@@ -155,27 +241,387 @@ impl Default for Config {
             http: Http {
                 socket: "127.0.0.1:8053".parse().unwrap(),
                 workers: 2 * ::num_cpus::get() as u16,
-                // log_level: (),
+                cache: default_cache(),
+                grace: default_grace(),
+                user: None,
+                group: None,
+                chroot: None,
+                log: Log::default(),
+                metrics: Metrics::default(),
+                lockout: Lockout::default(),
+                limits: Limits::default(),
+                listeners: Vec::new(),
             },
             dns: Dns {
                 socket: "127.0.0.1:53".parse().unwrap(),
                 ttl: 60 /*sec*/,
             },
+            hashing: Algorithm::default(),
+            audit: Audit::default(),
             users: Users::new(),
         }
     }
 }
 
+/// Applies the permissions and the owner of the existing file at the given path, if any, to the
+/// given file.
+fn keep_metadata(file: &File, path: &Path) -> io::Result<()> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    file.set_permissions(meta.permissions())?;
+    let current = file.metadata()?;
+    if (current.uid(), current.gid()) != (meta.uid(), meta.gid()) {
+        // Note: Only root may give a file away. Others may only change the group.
+        if unsafe { ::libc::fchown(file.as_raw_fd(), meta.uid(), meta.gid()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// A configuration shared between threads that can be replaced while they are using it.
+///
+/// Cloning a `Shared` yields another handle to the same configuration. Replacing the configuration
+/// does not affect anybody who still holds the old one.
+#[derive(Clone, Debug)]
+pub struct Shared(Arc<RwLock<Arc<Config>>>);
+
+impl Shared {
+    /// Creates a new handle to the given configuration.
+    pub fn new(config: Config) -> Self {
+        Shared(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    /// Returns the current configuration.
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Replaces the current configuration.
+    pub fn set(&self, config: Config) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
 /// A configuration for the HTTP server.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Http {
     /// The socket address (== IP address and port number).
     pub socket: SocketAddr,
 
-    /// The number of worker threads spinned up.
+    /// The number of threads verifying passwords. (Requests are handled by a single event loop.)
     pub workers: u16,
 
-    // pub log_level: (), // TODO Find a good type!
+    /// The time (in seconds) a successful verification of credentials is cached. `0` disables the
+    /// cache.
+    #[serde(default = "default_cache")]
+    pub cache: u64,
+
+    /// The time (in seconds) the server waits for updates in progress when shutting down.
+    #[serde(default = "default_grace")]
+    pub grace: u64,
+
+    /// The user the server runs as after binding its sockets. Without it, the server keeps
+    /// running as the user that started it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// The group the server runs as after binding its sockets. Defaults to the primary group of
+    /// the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// The directory the server changes its root directory to after binding its sockets, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chroot: Option<PathBuf>,
+
+    /// The logging configuration.
+    #[serde(default)]
+    pub log: Log,
+
+    /// The metrics endpoint configuration.
+    #[serde(default)]
+    pub metrics: Metrics,
+
+    /// The brute-force protection configuration.
+    #[serde(default)]
+    pub lockout: Lockout,
+
+    /// The rate limiting configuration.
+    #[serde(default)]
+    pub limits: Limits,
+
+    /// Additional listeners, e.g. for another IP address or a reverse proxy on the same host.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<Listener>,
+}
+
+fn default_cache() -> u64 {
+    300 /*sec*/
+}
+
+fn default_grace() -> u64 {
+    10 /*sec*/
+}
+
+/// A logging configuration.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Log {
+    /// The most verbose level that is logged.
+    #[serde(default)]
+    pub level: LogLevel,
+
+    /// The format of a log message.
+    #[serde(default)]
+    pub format: LogFormat,
+
+    /// The destination of the log messages.
+    #[serde(default)]
+    pub output: LogOutput,
+
+    /// The log file. (Only used if the output is `file`.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// A log level.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        LogLevel::Info
+    }
+}
+
+/// A log message format.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable text
+    Text,
+
+    /// One JSON object per message
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+/// A destination for log messages.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    /// The standard error stream
+    Stderr,
+
+    /// A file
+    File,
+
+    /// The local syslog daemon (or journald), listening at `/dev/log`
+    Syslog,
+}
+
+impl Default for LogOutput {
+    fn default() -> Self {
+        LogOutput::Stderr
+    }
+}
+
+/// A configuration for the metrics endpoint.
+///
+/// The metrics are never served alongside the other routes since they tell about every user's
+/// domains, but without authentication.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Metrics {
+    /// Whether the metrics are served at `/metrics`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// The separate socket address for `/metrics`.
+    #[serde(default = "default_metrics_socket")]
+    pub socket: SocketAddr,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            enabled: false,
+            socket: default_metrics_socket(),
+        }
+    }
+}
+
+fn default_metrics_socket() -> SocketAddr {
+    "127.0.0.1:9053".parse().unwrap()
+}
+
+/// An additional listener of the HTTP server.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Listener {
+    /// A TCP socket address (== IP address and port number).
+    Tcp {
+        /// The socket address.
+        socket: SocketAddr,
+    },
+
+    /// A Unix domain socket, e.g. for a reverse proxy on the same host.
+    Unix {
+        /// The path of the socket file.
+        path: PathBuf,
+
+        /// The permissions of the socket file as an octal number, e.g. `"660"`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<String>,
+
+        /// The user that owns the socket file.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner: Option<String>,
+
+        /// The group that owns the socket file.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+
+        /// The header the reverse proxy passes the client's address in, e.g. `"X-Real-IP"`.
+        /// Defaults to the last address within `X-Forwarded-For`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        header: Option<String>,
+    },
+}
+
+impl Listener {
+    /// Returns the permissions of the socket file of a Unix domain socket, if any.
+    pub fn mode(&self) -> Result<Option<u32>> {
+        match *self {
+            Listener::Unix { mode: Some(ref mode), .. } => match u32::from_str_radix(mode, 8) {
+                Ok(bits) if bits <= 0o777 => Ok(Some(bits)),
+                _ => bail!(ErrorKind::ConfigInvalid(format!("Invalid mode '{}'", mode))),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Listener::Tcp { socket } => write!(f, "{}", socket),
+            Listener::Unix { ref path, .. } => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A configuration for the brute-force protection.
+///
+/// Failed logins are counted per user and per client. As soon as either one fails too often
+/// within the time window, it is locked out for a while.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Lockout {
+    /// The number of failed logins that causes a lockout. `0` disables lockouts.
+    #[serde(default = "default_lockout_attempts")]
+    pub attempts: u32,
+
+    /// The time window (in seconds) in which failed logins are counted.
+    #[serde(default = "default_lockout_window")]
+    pub window: u64,
+
+    /// The duration (in seconds) of a lockout.
+    #[serde(default = "default_lockout_duration")]
+    pub duration: u64,
+
+    /// The delay (in milliseconds) before responding to a failed login.
+    #[serde(default = "default_lockout_delay")]
+    pub delay: u64,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Lockout {
+            attempts: default_lockout_attempts(),
+            window: default_lockout_window(),
+            duration: default_lockout_duration(),
+            delay: default_lockout_delay(),
+        }
+    }
+}
+
+fn default_lockout_attempts() -> u32 {
+    5
+}
+
+fn default_lockout_window() -> u64 {
+    300 /*sec*/
+}
+
+fn default_lockout_duration() -> u64 {
+    900 /*sec*/
+}
+
+fn default_lockout_delay() -> u64 {
+    1000 /*ms*/
+}
+
+/// A configuration for the rate limiting of updates.
+///
+/// Updates are limited per user and per domain using token buckets. Every update takes a token
+/// from the user's and from the domain's bucket. Updates are refused while either bucket is empty.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Limits {
+    /// The token bucket per user.
+    #[serde(default = "default_user_limit")]
+    pub user: Limit,
+
+    /// The token bucket per domain.
+    #[serde(default = "default_domain_limit")]
+    pub domain: Limit,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            user: default_user_limit(),
+            domain: default_domain_limit(),
+        }
+    }
+}
+
+fn default_user_limit() -> Limit {
+    Limit {
+        burst: 60,
+        interval: 1 /*sec*/,
+    }
+}
+
+fn default_domain_limit() -> Limit {
+    Limit {
+        burst: 10,
+        interval: 60 /*sec*/,
+    }
+}
+
+/// A configuration for a token bucket.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct Limit {
+    /// The capacity of the bucket, i.e. the number of updates in a row. `0` disables the limit.
+    pub burst: u32,
+
+    /// The time (in seconds) it takes to refill one token.
+    pub interval: u64,
 }
 
 /// A configuration for the DNS update client.
@@ -188,6 +634,14 @@ pub struct Dns {
     pub ttl: u32,
 }
 
+/// A configuration for the audit log.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Audit {
+    /// The audit log file. Without a file, no audit log is written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
 /// A mapping from users (== names) to settings (== passwords and lists of domains the user is
 /// authorized for).
 #[derive(Debug, Deserialize, Serialize)]
@@ -232,6 +686,26 @@ pub struct User {
     /// The salted and cryptographically hashed password.
     pub pw: Hash,
 
+    /// The secret used to sign update URLs, if any has been generated yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+
+    /// A list of address blocks the user may send requests from. An empty list allows any client.
+    #[serde(default)]
+    pub clients: Cidrs,
+
+    /// A list of address blocks the user may point domains to. An empty list allows any address.
+    ///
+    /// Like `public_only`, this applies to all of the user's domains; there are no rules per
+    /// domain. A domain that needs other rules requires a separate user.
+    #[serde(default)]
+    pub targets: Cidrs,
+
+    /// Whether the user may only point domains to public addresses, i.e. not to private,
+    /// loopback, link-local or any other special-purpose addresses.
+    #[serde(default)]
+    pub public_only: bool,
+
     /// A list of domains the user is authorized for.
     pub domains: Domains,
 }
@@ -239,11 +713,76 @@ pub struct User {
 impl User {
     /// Creates a new user setting with a given password and an empty list of authorized domains.
     pub fn with_pw(pw: &str) -> Self {
+        Self::with_hash(pw.into())
+    }
+
+    /// Creates a new user setting with a given, already hashed password and an empty list of
+    /// authorized domains.
+    pub fn with_hash(pw: Hash) -> Self {
         Self {
-            pw: pw.into(),
+            pw,
+            secret: None,
+            clients: Cidrs::new(),
+            targets: Cidrs::new(),
+            public_only: false,
             domains: Domains::new(),
         }
     }
+
+    /// Checks whether the user may send requests from the given IP address.
+    pub fn allows_client(&self, ip: IpAddr) -> bool {
+        self.clients.is_empty() || self.clients.includes(ip)
+    }
+
+    /// Checks whether the user may point a domain to the given IP address.
+    pub fn allows_target(&self, ip: IpAddr) -> bool {
+        if self.public_only && is_special(ip) {
+            return false;
+        }
+        self.targets.is_empty() || self.targets.includes(ip)
+    }
+}
+
+lazy_static! {
+    /// Address blocks reserved for special purposes, e.g. private networks, loopback or
+    /// link-local addresses (see RFC 6890 and the IANA special-purpose address registries).
+    static ref SPECIAL: Vec<Cidr> = [
+        // IPv4
+        "0.0.0.0/8",        // "This network"
+        "10.0.0.0/8",       // Private-Use
+        "100.64.0.0/10",    // Shared Address Space
+        "127.0.0.0/8",      // Loopback
+        "169.254.0.0/16",   // Link Local
+        "172.16.0.0/12",    // Private-Use
+        "192.0.0.0/24",     // IETF Protocol Assignments
+        "192.0.2.0/24",     // Documentation (TEST-NET-1)
+        "192.88.99.0/24",   // Deprecated 6to4 Relay Anycast
+        "192.168.0.0/16",   // Private-Use
+        "198.18.0.0/15",    // Benchmarking
+        "198.51.100.0/24",  // Documentation (TEST-NET-2)
+        "203.0.113.0/24",   // Documentation (TEST-NET-3)
+        "224.0.0.0/4",      // Multicast
+        "240.0.0.0/4",      // Reserved (incl. Limited Broadcast)
+
+        // IPv6
+        "::/128",           // Unspecified Address
+        "::1/128",          // Loopback Address
+        "64:ff9b::/96",     // IPv4-IPv6 Translation
+        "64:ff9b:1::/48",   // Local-Use IPv4/IPv6 Translation
+        "100::/64",         // Discard-Only Address Block
+        "2001::/23",        // IETF Protocol Assignments (incl. Teredo)
+        "2001:db8::/32",    // Documentation
+        "2002::/16",        // 6to4
+        "3fff::/20",        // Documentation
+        "fc00::/7",         // Unique-Local
+        "fe80::/10",        // Linked-Scoped Unicast
+        "ff00::/8",         // Multicast
+    ].iter().map(|cidr| cidr.parse().unwrap()).collect();
+}
+
+/// Checks whether the given IP address is reserved for special purposes.
+fn is_special(ip: IpAddr) -> bool {
+    SPECIAL.iter().any(|cidr| cidr.contains(ip))
 }
 
 /// A set of domains a user is authorized for.
@@ -281,6 +820,46 @@ impl DerefMut for Domains {
     }
 }
 
+/// A set of address blocks.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Cidrs(HashSet<Cidr>);
+
+impl Cidrs {
+    /// Create a new, empty set of address blocks.
+    pub fn new() -> Self {
+        Cidrs(HashSet::new())
+    }
+
+    /// Adds a given address block to the set.
+    pub fn add(&mut self, cidr: Cidr) -> bool {
+        self.insert(cidr)
+    }
+
+    /// Removes a given address block from the set.
+    pub fn rm(&mut self, cidr: &Cidr) -> bool {
+        self.remove(cidr)
+    }
+
+    /// Checks whether any address block in the set contains the given IP address.
+    pub fn includes(&self, ip: IpAddr) -> bool {
+        self.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+impl Deref for Cidrs {
+    type Target = HashSet<Cidr>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Cidrs {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -292,6 +871,20 @@ mod tests {
         // TODO test other fields
     }
 
+    #[test]
+    fn limits_partial() {
+        let limits: Limits = toml::from_str("[user]\nburst = 5\ninterval = 2\n").unwrap();
+        assert_eq!((limits.user.burst, limits.user.interval), (5, 2));
+        assert_eq!((limits.domain.burst, limits.domain.interval), (10, 60));
+    }
+
+    #[test]
+    fn lockout_partial() {
+        let lockout: Lockout = toml::from_str("attempts = 10\n").unwrap();
+        assert_eq!(lockout.attempts, 10);
+        assert_eq!((lockout.window, lockout.duration, lockout.delay), (300, 900, 1000));
+    }
+
     #[test]
     fn config_add_one_user() {
         // Create an empty config
@@ -353,4 +946,132 @@ mod tests {
         assert!(config.user("tobias").unwrap().pw.is("1234"));
         assert!(config.user("sebastian").unwrap().pw.is("4321"));
     }
+
+    #[test]
+    fn config_validate() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+
+        config.http.log.output = LogOutput::File;
+        assert!(config.validate().is_err());
+        config.http.log.file = Some("dynonym.log".into());
+        assert!(config.validate().is_ok());
+
+        config.http.chroot = Some("/var/lib/dynonym".into());
+        assert!(config.validate().is_err());
+        config.http.user = Some("dynonym".into());
+        assert!(config.validate().is_ok());
+        config.http.chroot = None;
+        config.http.user = None;
+
+        config.http.metrics.socket = config.http.socket;
+        assert!(config.validate().is_ok());
+        config.http.metrics.enabled = true;
+        assert!(config.validate().is_err());
+        config.http.metrics = Metrics::default();
+
+        let unix = Listener::Unix {
+            path: "dynonym.sock".into(),
+            mode: Some("660".into()),
+            owner: None,
+            group: None,
+            header: None,
+        };
+        assert_eq!(unix.mode().unwrap(), Some(0o660));
+        config.http.listeners = vec![unix];
+        assert!(config.validate().is_ok());
+
+        config.http.listeners.push(Listener::Tcp { socket: config.http.socket });
+        assert!(config.validate().is_err());
+        config.http.listeners = vec![Listener::Unix {
+            path: "dynonym.sock".into(),
+            mode: Some("999".into()),
+            owner: None,
+            group: None,
+            header: None,
+        }];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn config_validate_hashing() {
+        let mut config = Config::default();
+        config.hashing = Algorithm::Bcrypt { cost: 3 };
+        assert!(config.validate().is_err());
+        config.hashing = Algorithm::Bcrypt { cost: 4 };
+        assert!(config.validate().is_ok());
+
+        config.hashing = Algorithm::Argon2id { memory: 19456, iterations: 2, lanes: 0 };
+        assert!(config.validate().is_err());
+        config.hashing = Algorithm::Argon2id { memory: 15, iterations: 2, lanes: 2 };
+        assert!(config.validate().is_err());
+        config.hashing = Algorithm::Argon2id { memory: 19456, iterations: 0, lanes: 1 };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn config_file_replace() {
+        use std::os::unix::fs::PermissionsExt;
+        use tempfile::NamedTempFile;
+
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o640)).unwrap();
+        Config::default().store(path).unwrap();
+
+        assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert!(!Path::new(&format!("{}.tmp", path.display())).exists());
+        assert!(Config::load(path).is_ok());
+    }
+
+    #[test]
+    fn shared_set() {
+        let shared = Shared::new(Config::default());
+        let old = shared.get();
+
+        let mut config = Config::default();
+        config.users.add("tobias", "1234");
+        shared.clone().set(config);
+        assert!(old.user("tobias").is_none());
+        assert!(shared.get().user("tobias").is_some());
+    }
+
+    #[test]
+    fn user_allows_client() {
+        let mut user = User::with_pw("1234");
+        assert!(user.allows_client("198.51.100.1".parse().unwrap()));
+
+        user.clients.add("192.0.2.0/24".parse().unwrap());
+        assert!(user.allows_client("192.0.2.1".parse().unwrap()));
+        assert!(!user.allows_client("198.51.100.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn user_allows_target() {
+        let mut user = User::with_pw("1234");
+        assert!(user.allows_target("10.0.0.1".parse().unwrap()));
+        assert!(user.allows_target("198.51.100.1".parse().unwrap()));
+
+        user.targets.add("198.51.100.0/24".parse().unwrap());
+        assert!(user.allows_target("198.51.100.1".parse().unwrap()));
+        assert!(!user.allows_target("203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn user_allows_target_public_only() {
+        let mut user = User::with_pw("1234");
+        user.public_only = true;
+        assert!(user.allows_target("1.1.1.1".parse().unwrap()));
+        assert!(user.allows_target("2606:4700::1111".parse().unwrap()));
+        assert!(!user.allows_target("10.0.0.1".parse().unwrap()));
+        assert!(!user.allows_target("127.0.0.1".parse().unwrap()));
+        assert!(!user.allows_target("169.254.1.1".parse().unwrap()));
+        assert!(!user.allows_target("::1".parse().unwrap()));
+        assert!(!user.allows_target("fe80::1".parse().unwrap()));
+        assert!(!user.allows_target("::ffff:192.168.0.1".parse().unwrap()));
+        assert!(!user.allows_target("64:ff9b::a00:1".parse().unwrap()));
+        assert!(!user.allows_target("2001::1".parse().unwrap()));
+        assert!(!user.allows_target("2002:a00:1::1".parse().unwrap()));
+        assert!(!user.allows_target("192.88.99.1".parse().unwrap()));
+    }
 }
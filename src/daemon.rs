@@ -0,0 +1,159 @@
+//! Daemon mode
+//!
+//! Without a service manager, a server is usually turned into a daemon, i.e. a background process
+//! that is detached from the terminal. [`daemonize`] does so the classic way:
+//!
+//! 1. It forks, so that the original process can wait for the daemon to start up.
+//! 2. It creates a new session (`setsid`), detaching from the terminal.
+//! 3. It forks again, so that the daemon can never acquire a terminal again.
+//! 4. It changes the working directory to `/` and redirects the standard streams.
+//!
+//! The original process exits as soon as the daemon is [`ready`] or [`fail`]ed. Its exit code
+//! tells whether the daemon started up successfully.
+//!
+//! [`daemonize`]: fn.daemonize.html
+//! [`ready`]: fn.ready.html
+//! [`fail`]: fn.fail.html
+
+use errors::*;
+
+use libc::{self, c_int};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The file that replaces the standard input (and the standard output without a log file).
+const DEV_NULL: &str = "/dev/null";
+
+/// The message a daemon sends to the original process as soon as it is ready.
+const READY: &[u8] = b"\0";
+
+/// The file descriptor of the pipe to the original process, plus one. (`0` means none.)
+static PIPE: AtomicUsize = AtomicUsize::new(0);
+
+/// Turns the current process into a daemon whose standard output and error streams are redirected
+/// to the given log file, if any.
+///
+/// Only returns within the daemon. The original process exits as soon as the daemon is ready or
+/// fails, but it returns an error if the daemon fails.
+pub fn daemonize(log_file: Option<&Path>) -> Result<()> {
+    // Open the files for the standard streams, reporting errors before forking
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(DEV_NULL)
+        .chain_err(|| ErrorKind::DaemonStdio(DEV_NULL.into()))?;
+    let output = match log_file {
+        Some(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .chain_err(|| ErrorKind::DaemonStdio(path.into()))?,
+        None => null.try_clone().chain_err(|| ErrorKind::DaemonStdio(DEV_NULL.into()))?,
+    };
+
+    // Create a pipe through which the daemon reports its startup
+    // Note: Any process the daemon executes must not inherit the pipe, keeping it open.
+    let mut fds = [0; 2];
+    check(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })
+        .chain_err(|| ErrorKind::DaemonFork)?;
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // Fork and wait for the daemon within the original process
+    let pid = check_pid(unsafe { libc::fork() }).chain_err(|| ErrorKind::DaemonFork)?;
+    if pid > 0 {
+        drop(writer);
+        return wait(pid, reader);
+    }
+    drop(reader);
+    PIPE.store(writer.as_raw_fd() as usize + 1, Ordering::SeqCst);
+    mem::forget(writer);     // Note: The pipe is closed by `ready` or `fail`.
+
+    // Note: From now on, the original process learns about an error through the pipe only.
+    let detached = detach(&null, &output);
+    if let Err(ref err) = detached {
+        fail(err);
+    }
+    detached
+}
+
+/// Detaches the current process from the terminal, forks again and redirects the standard streams
+/// to the given files. Only returns within the daemon.
+fn detach(null: &File, output: &File) -> Result<()> {
+    // Detach from the terminal and fork again
+    check_pid(unsafe { libc::setsid() }).chain_err(|| ErrorKind::DaemonSetsid)?;
+    let pid = check_pid(unsafe { libc::fork() }).chain_err(|| ErrorKind::DaemonFork)?;
+    if pid > 0 {
+        // Note: The intermediate process must not run any destructors.
+        unsafe { libc::_exit(0) };
+    }
+
+    // Change the working directory and redirect the standard streams
+    env::set_current_dir("/").chain_err(|| ErrorKind::DaemonChdir)?;
+    for &(file, stream) in &[(null, 0), (output, 1), (output, 2)] {
+        check(unsafe { libc::dup2(file.as_raw_fd(), stream) })
+            .chain_err(|| ErrorKind::DaemonRedirect(stream))?;
+    }
+
+    Ok(())
+}
+
+/// Tells the original process that the daemon started up successfully, if the current process is
+/// a daemon.
+pub fn ready() {
+    report(READY);
+}
+
+/// Tells the original process that the daemon failed to start up because of the given error, if
+/// the current process is a daemon.
+pub fn fail(err: &Error) {
+    report(err.describe().as_bytes());
+}
+
+/// Sends the given message to the original process and closes the pipe, unless it is closed
+/// already.
+fn report(message: &[u8]) {
+    let fd = PIPE.swap(0, Ordering::SeqCst);
+    if fd > 0 {
+        let mut pipe = unsafe { File::from_raw_fd(fd as c_int - 1) };
+        let _ = pipe.write_all(message);  // Note: Nobody is left to report an error to.
+    }
+}
+
+/// Waits for the daemon to start up within the original process.
+fn wait(pid: libc::pid_t, mut reader: File) -> Result<()> {
+    // Reap the intermediate process
+    unsafe { libc::waitpid(pid, ptr::null_mut(), 0) };
+
+    // Read the report
+    // Note: The pipe is closed without any report if the daemon dies unexpectedly.
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).chain_err(|| ErrorKind::DaemonFork)?;
+    if buf == READY {
+        process::exit(0);
+    }
+    let reason = match buf.len() {
+        0 => "The daemon exited unexpectedly".into(),
+        _ => String::from_utf8_lossy(&buf).into_owned(),
+    };
+    bail!(ErrorKind::DaemonStartup(reason))
+}
+
+/// Turns the given return value of a libc function into a result.
+fn check(ret: c_int) -> io::Result<()> {
+    check_pid(ret).map(|_| ())
+}
+
+/// Turns the given process ID returned by a libc function into a result.
+fn check_pid(pid: libc::pid_t) -> io::Result<libc::pid_t> {
+    match pid {
+        -1 => Err(io::Error::last_os_error()),
+        pid => Ok(pid),
+    }
+}
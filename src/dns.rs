@@ -1,116 +1,351 @@
 //! Domain name system update client (RFC 2136 "DNS UPDATE")
 
 use errors::*;
-use types::Domain;
+use metrics::Registry;
+use types::{Domain, Rcode};
 
-use std::convert::TryInto;
+use futures::Future;
+use futures::future::{self, Either, Loop};
+use std::fmt::Debug;
+use std::io;
 use std::net::IpAddr::{self, V4, V6};
 use std::net::SocketAddr;
-use trust_dns::client::{Client, SyncClient};
-use trust_dns::op::ResponseCode;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio_core::reactor::Handle;
+use trust_dns::client::{BasicClientHandle, ClientFuture, ClientHandle};
+use trust_dns::error::{ClientError, ClientErrorKind};
+use trust_dns::op::{Message, ResponseCode};
 use trust_dns::rr::{DNSClass, Name, RData, Record, RecordType};
-use trust_dns::udp::UdpClientConnection;
+use trust_dns::udp::UdpClientStream;
+use trust_dns_proto::error::ProtoErrorKind;
 
+/// The outcome of a successful update.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The record was changed.
+    Changed,
+
+    /// The record already pointed to the IP address. No update was sent.
+    Unchanged,
+}
+
+/// A client that updates the records served by a DNS server.
+///
+/// Every request is sent asynchronously on the reactor whose handle was given when creating the
+/// updater. Cloning an updater yields another handle to the same connection.
+#[derive(Clone)]
 pub struct Updater {
-    client: SyncClient<UdpClientConnection>,
+    client: BasicClientHandle,
     ttl: u32,
+    metrics: Option<Arc<Registry>>,
 }
 
 impl Updater {
-    pub fn new(socket: SocketAddr, ttl: u32) -> Result<Self> {
+    pub fn new(socket: SocketAddr, ttl: u32, handle: &Handle) -> Self {
         // Open a connection
-        let conn = UdpClientConnection::new(socket)
-            .chain_err(|| ErrorKind::DnsConnOpen(socket))?;
+        let (stream, sender) = UdpClientStream::new(socket, handle);
 
         // Create a new updater
-        let updater = Self {
-            client: SyncClient::new(conn),
+        Self {
+            client: ClientFuture::new(stream, sender, handle, None),
             ttl,
+            metrics: None,
+        }
+    }
+
+    /// Records the UPDATE messages and SOA queries sent by this updater in the given registry.
+    pub fn with_metrics(mut self, metrics: Arc<Registry>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn update(&self, domain: Domain, ip: IpAddr) -> Box<Future<Item = Outcome, Error = Error>> {
+        // Convert domain into the Trust DNS format
+        let domain0 = match domain.to_name() {
+            Ok(domain0) => domain0,
+            Err(err) => return Box::new(future::err(
+                Error::with_chain(err, ErrorKind::DnsDomainConvert(domain))
+            )),
         };
 
-        // Return
-        Ok(updater)
+        // Skip the update if the record already points to the IP address
+        let rr_type = match ip {
+            V4(_) => RecordType::A,
+            V6(_) => RecordType::AAAA,
+        };
+        let this = self.clone();
+        let future = self.query_ips(domain0.clone(), rr_type).and_then(move |ips| {
+            if ips == [ip] {
+                info!("'{}' already points to '{}', skipping the update", domain0, ip);
+                return Either::A(future::ok(Outcome::Unchanged));
+            }
+            Either::B(this.replace(domain0, ip, rr_type).map(|_| Outcome::Changed))
+        });
+        Box::new(future)
     }
 
-    pub fn update(&self, domain: Domain, ip: IpAddr) -> Result<()> {
+    pub fn query(&self, domain: Domain) -> Box<Future<Item = Vec<IpAddr>, Error = Error>> {
         // Convert domain into the Trust DNS format
-        let domain0 = domain.clone().try_into()
-            .chain_err(|| ErrorKind::DnsDomainConvert(domain))?;
+        let domain0 = match domain.to_name() {
+            Ok(domain0) => domain0,
+            Err(err) => return Box::new(future::err(
+                Error::with_chain(err, ErrorKind::DnsDomainConvert(domain))
+            )),
+        };
+
+        // Query both address records
+        let future = self.query_ips(domain0.clone(), RecordType::A)
+            .join(self.query_ips(domain0, RecordType::AAAA))
+            .map(|(mut ips, ipv6s)| {
+                ips.extend(ipv6s);
+                ips
+            });
+        Box::new(future)
+    }
+
+    /// Sends an SOA query for the root zone in order to check whether the DNS server responds and
+    /// returns the response code.
+    pub fn probe(&self) -> Box<Future<Item = Rcode, Error = Error>> {
+        debug!("Probing the DNS server");
+        let future = self.client.clone().query(Name::root(), DNSClass::IN, RecordType::SOA)
+            .map_err(convert)
+            .then(|result| -> Result<Rcode> {
+                let response = result.chain_err(|| ErrorKind::DnsRecordQuery)?;
+                Ok(Rcode::from(response.response_code()))
+            });
+        Box::new(future)
+    }
 
+    /// Replaces the record of the given type by a record pointing to the given IP address.
+    fn replace(&self, domain: Name, ip: IpAddr, rr_type: RecordType)
+        -> Box<Future<Item = (), Error = Error>>
+    {
         // Find the zone name to update, i.e. SOA name
-        let zone = self.find_zone(&domain0)?;
-
-        // Remove the existing record
-        {
-            // Assemble the (pseudo) record
-            let mut record = Record::new();
-            record.set_name(domain0.clone());
-            record.set_ttl(0);
-            match ip {
-                V4(_) => record.set_rr_type(RecordType::A),
-                V6(_) => record.set_rr_type(RecordType::AAAA),
-            };
+        let this = self.clone();
+        let future = self.find_zone(domain.clone()).and_then(move |zone| {
+            debug!("Updating '{}' in zone '{}'", domain, zone);
 
-            // Send the request
-            let result = self.client.delete_rrset(record, zone.clone())
-                .chain_err(|| ErrorKind::DnsRecordDelete)?;
-            if result.response_code() != ResponseCode::NoError {
-                bail!(ErrorKind::DnsRecordDelete);  // TODO Add more information about the error!
-            }
-        }
+            // Remove the existing record
+            let delete = {
+                // Assemble the (pseudo) record
+                let mut record = Record::new();
+                record.set_name(domain.clone());
+                record.set_ttl(0);
+                record.set_rr_type(rr_type);
+
+                // Send the request
+                let request = this.client.clone().delete_rrset(record, zone.clone());
+                this.send(zone.clone(), request)
+                    .map_err(|err| Error::with_chain(err, ErrorKind::DnsRecordDelete))
+            };
 
-        // Create a new record
-        {
-            // Assemble the record
-            let mut record = Record::new();
-            record.set_name(domain0);
-            record.set_ttl(self.ttl);
-            match ip {
-                V4(ipv4) => {
-                    record.set_rr_type(RecordType::A);
-                    record.set_rdata(RData::A(ipv4));
-                },
-                V6(ipv6) => {
-                    record.set_rr_type(RecordType::AAAA);
-                    record.set_rdata(RData::AAAA(ipv6));
+            // Create a new record
+            let create = {
+                // Assemble the record
+                let mut record = Record::new();
+                record.set_name(domain.clone());
+                record.set_ttl(this.ttl);
+                match ip {
+                    V4(ipv4) => {
+                        record.set_rr_type(RecordType::A);
+                        record.set_rdata(RData::A(ipv4));
+                    },
+                    V6(ipv6) => {
+                        record.set_rr_type(RecordType::AAAA);
+                        record.set_rdata(RData::AAAA(ipv6));
+                    }
                 }
-            }
 
-            // Send the request
-            let result = self.client.create(record, zone)
-                .chain_err(|| ErrorKind::DnsRecordCreate)?;
-            if result.response_code() != ResponseCode::NoError {
-                bail!(ErrorKind::DnsRecordCreate);  // TODO Add more information about the error!
-            }
-        }
+                // Send the request, but only after the existing record is gone
+                move |_| {
+                    let request = this.client.clone().create(record, zone.clone());
+                    this.send(zone, request)
+                        .map_err(|err| Error::with_chain(err, ErrorKind::DnsRecordCreate))
+                }
+            };
 
-        Ok(())
+            delete.and_then(create).map(move |_| info!("Updated '{}' to '{}'", domain, ip))
+        });
+        Box::new(future)
     }
 
-    fn find_zone(&self, domain: &Name) -> Result<Name> {
-        for domain in
-            (0..domain.num_labels() + 1)
-                .rev()
-                .map(|x| domain.trim_to(x as usize))
-        {
-            let response = self.client.query(&domain, DNSClass::IN, RecordType::SOA)
-                .chain_err(|| ErrorKind::DnsRecordQuery)?;
-            let record =
-                response
+    fn query_ips(&self, domain: Name, rr_type: RecordType)
+        -> Box<Future<Item = Vec<IpAddr>, Error = Error>>
+    {
+        debug!("Querying the {:?} records of '{}'", rr_type, domain);
+        let future = self.client.clone().query(domain, DNSClass::IN, rr_type)
+            .map_err(convert)
+            .then(|result| -> Result<Vec<IpAddr>> {
+                let response = result.chain_err(|| ErrorKind::DnsRecordQuery)?;
+                let ips = response
                     .answers().iter()
-                    .chain(
+                    .filter_map(|record| match *record.rdata() {
+                        RData::A(ipv4) => Some(V4(ipv4)),
+                        RData::AAAA(ipv6) => Some(V6(ipv6)),
+                        _ => None,
+                    })
+                    .collect();
+                Ok(ips)
+            });
+        Box::new(future)
+    }
+
+    fn find_zone(&self, domain: Name) -> Box<Future<Item = Name, Error = Error>> {
+        let this = self.clone();
+        let future = future::loop_fn(domain.num_labels(), move |labels| {
+            let domain = domain.trim_to(labels as usize);
+            debug!("Querying the SOA record of '{}'", domain);
+            if let Some(ref metrics) = this.metrics {
+                metrics.soa_query();
+            }
+            this.client.clone().query(domain.clone(), DNSClass::IN, RecordType::SOA)
+                .map_err(convert)
+                .then(move |result| -> Result<Loop<Name, u8>> {
+                    let response = result.chain_err(|| ErrorKind::DnsRecordQuery)?;
+                    let record =
                         response
-                            .name_servers().iter()
-                            .filter(|x| x.rr_type() == RecordType::SOA)
-                    )
-                    .next()
-            ;
-            if let Some(record) = record {
-                let soa = record.name().clone();
-                assert!(soa.zone_of(&domain));
-                return Ok(soa);
+                            .answers().iter()
+                            .chain(
+                                response
+                                    .name_servers().iter()
+                                    .filter(|x| x.rr_type() == RecordType::SOA)
+                            )
+                            .next()
+                    ;
+
+                    // Note: The root zone is the last resort. A DNS server that does not even
+                    // know the root zone must not take the whole server down.
+                    match record.map(|record| record.name().clone()) {
+                        Some(soa) if soa.zone_of(&domain) => Ok(Loop::Break(soa)),
+                        None if labels > 0 => Ok(Loop::Continue(labels - 1)),
+                        _ => bail!(ErrorKind::DnsRecordQuery),
+                    }
+                })
+        });
+        Box::new(future)
+    }
+
+    /// Sends the given UPDATE message for the given zone, records it if there is a registry and
+    /// checks the response code.
+    fn send(&self, zone: Name, request: Box<Future<Item = Message, Error = ClientError>>)
+        -> Box<Future<Item = (), Error = Error>>
+    {
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        let future = request.map_err(convert).then(move |result| {
+            let rcode = result.as_ref().ok().map(|response| Rcode::from(response.response_code()));
+            if let Some(metrics) = metrics {
+                metrics.dns_update(&zone, start.elapsed(), rcode);
             }
+            check(result?.response_code())
+        });
+        Box::new(future)
+    }
+}
+
+/// A backend that performs the DNS operations of the HTTP server.
+///
+/// Whenever the server talks to a DNS server, it asks its backend for a [`Client`]. The default
+/// backend, [`Rfc2136`], sends DNS messages using an [`Updater`]. Another backend may, e.g.,
+/// update the records via the API of a DNS provider or keep them in memory for tests.
+///
+/// [`Client`]: trait.Client.html
+/// [`Rfc2136`]: struct.Rfc2136.html
+/// [`Updater`]: struct.Updater.html
+pub trait Backend: Debug + Send + Sync {
+    /// Returns a client for the DNS server at the given socket address that runs on the event
+    /// loop of the given handle and updates records using the given TTL.
+    fn connect(&self, socket: SocketAddr, ttl: u32, handle: &Handle) -> Box<Client>;
+}
+
+/// A client of a DNS server, as returned by a [`Backend`].
+///
+/// [`Backend`]: trait.Backend.html
+pub trait Client {
+    /// Points the given domain to the given IP address.
+    fn update(&self, domain: Domain, ip: IpAddr) -> Box<Future<Item = Outcome, Error = Error>>;
+
+    /// Returns the IP addresses the given domain currently points to.
+    fn query(&self, domain: Domain) -> Box<Future<Item = Vec<IpAddr>, Error = Error>>;
+
+    /// Checks whether the DNS server responds and returns the response code.
+    fn probe(&self) -> Box<Future<Item = Rcode, Error = Error>>;
+}
+
+impl Client for Updater {
+    fn update(&self, domain: Domain, ip: IpAddr) -> Box<Future<Item = Outcome, Error = Error>> {
+        Updater::update(self, domain, ip)
+    }
+
+    fn query(&self, domain: Domain) -> Box<Future<Item = Vec<IpAddr>, Error = Error>> {
+        Updater::query(self, domain)
+    }
+
+    fn probe(&self) -> Box<Future<Item = Rcode, Error = Error>> {
+        Updater::probe(self)
+    }
+}
+
+/// The default backend, which sends DNS UPDATE messages (RFC 2136) using an [`Updater`].
+///
+/// [`Updater`]: struct.Updater.html
+#[derive(Clone, Debug, Default)]
+pub struct Rfc2136 {
+    metrics: Option<Arc<Registry>>,
+}
+
+impl Rfc2136 {
+    /// Creates a new backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the messages sent by the clients of this backend in the given registry.
+    pub fn with_metrics(mut self, metrics: Arc<Registry>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
+
+impl Backend for Rfc2136 {
+    fn connect(&self, socket: SocketAddr, ttl: u32, handle: &Handle) -> Box<Client> {
+        let updater = Updater::new(socket, ttl, handle);
+        match self.metrics {
+            Some(ref metrics) => Box::new(updater.with_metrics(metrics.clone())),
+            None => Box::new(updater),
         }
-        unreachable!()
+    }
+}
+
+/// Turns a response code other than `NoError` into an error.
+fn check(rcode: ResponseCode) -> Result<()> {
+    match rcode {
+        ResponseCode::NoError => Ok(()),
+        rcode => {
+            warn!("DNS server responded with '{}'", Rcode::from(rcode));
+            bail!(ErrorKind::DnsResponse(Rcode::from(rcode)))
+        },
+    }
+}
+
+/// Converts a client error into an error, recognizing timeouts.
+fn convert(err: ClientError) -> Error {
+    let timeout = match *err.kind() {
+        ClientErrorKind::Timeout | ClientErrorKind::Proto(ProtoErrorKind::Timeout) => true,
+        // Note: The I/O error is the cause of the client error.
+        ClientErrorKind::Io | ClientErrorKind::Proto(ProtoErrorKind::Io) => (err.1).0
+            .as_ref()
+            .and_then(|cause| cause.downcast_ref::<io::Error>())
+            .map_or(false, |cause| {
+                cause.kind() == io::ErrorKind::TimedOut || cause.kind() == io::ErrorKind::WouldBlock
+            }),
+        _ => false,
+    };
+    if timeout {
+        warn!("DNS server did not respond in time");
+        Error::with_chain(err, ErrorKind::DnsTimeout)
+    } else {
+        err.into()
     }
 }
@@ -5,26 +5,71 @@
 //!
 //! [error-chain]: ../../error_chain/index.html
 
-use types::Domain;
+use types::{Domain, Rcode};
 
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
 error_chain! {
     foreign_links {
-        Ctrlc(::ctrlc::Error)                   #[doc = "Error when setting up SIGINT handler."];
         Dns(::trust_dns::error::ClientError)    #[doc = "Error during DNS operation."];
         Io(::std::io::Error)                    #[doc = "Error during IO."];
         ParseInt(::std::num::ParseIntError)     #[doc = "Error when parsing an integer."];
         ParseNetAddr(::std::net::AddrParseError)
             #[doc = "Error when parsing an IP or socket address."];
-        RocketConfig(::rocket::config::ConfigError)
-            #[doc = "Error when creating a Rocket configuration."];
         TomlDe(::toml::de::Error)               #[doc = "Error when deserializing TOML."];
         TomlSer(::toml::ser::Error)             #[doc = "Error when serializing TOML."];
     }
 
     errors {
+        /// Error when opening an audit log file.
+        AuditFileOpen(path: PathBuf) {
+            description("Cannot open audit log file")
+            display("Cannot open audit log file '{}'", path.display())
+        }
+
+        /// Error when locking an audit log file.
+        AuditFileLock(path: PathBuf) {
+            description("Cannot lock audit log file")
+            display("Cannot lock audit log file '{}'", path.display())
+        }
+
+        /// Error when reading an audit log file.
+        AuditFileRead(path: PathBuf) {
+            description("Cannot read audit log file")
+            display("Cannot read audit log file '{}'", path.display())
+        }
+
+        /// Error when writing an audit log file.
+        AuditFileWrite(path: PathBuf) {
+            description("Cannot write audit log file")
+            display("Cannot write audit log file '{}'", path.display())
+        }
+
+        /// Error when decoding a line of an audit log file.
+        AuditFileDecode(path: PathBuf, line: usize) {
+            description("Cannot decode audit log file")
+            display("Cannot decode line {} of audit log file '{}'", line, path.display())
+        }
+
+        /// Error when an audit log file was tampered with.
+        AuditFileTampered(path: PathBuf, line: usize) {
+            description("Audit log file was tampered with")
+            display("Audit log file '{}' was tampered with at line {}", path.display(), line)
+        }
+
+        /// Error when an audit log file was truncated.
+        AuditFileTruncated(path: PathBuf) {
+            description("Audit log file was truncated")
+            display("Audit log file '{}' was truncated", path.display())
+        }
+
+        /// Error when parsing a CIDR block.
+        CidrParse(cidr: String) {
+            description("Cannot parse CIDR block")
+            display("Cannot parse CIDR block '{}'", cidr)
+        }
+
         /// Error when opening a configuration file.
         ConfigFileOpen(path: PathBuf) {
             description("Cannot open config file")
@@ -61,10 +106,54 @@ error_chain! {
             display("Cannot encode config file '{}'", path.display())
         }
 
-        /// Error when opening a connection to a DNS server.
-        DnsConnOpen(socket: SocketAddr) {
-            description("Cannot open a connection to the DNS server")
-            display("Cannot open a connection to the DNS server at '{}'", socket)
+        /// Error when a configuration is invalid.
+        ConfigInvalid(reason: String) {
+            description("Invalid configuration")
+            display("Invalid configuration: {}", reason)
+        }
+
+        /// Error when reloading a configuration whose changes require a restart.
+        ConfigRestart(sections: String) {
+            description("Changes to the configuration require a restart")
+            display("Changes to {} require a restart", sections)
+        }
+
+        /// Error when setting up a SIGHUP handler for reloading the configuration.
+        ConfigSetupSighupHandler {
+            description("Cannot setup SIGHUP handler")
+        }
+
+        /// Error when changing the working directory of a daemon.
+        DaemonChdir {
+            description("Cannot change working directory of daemon")
+        }
+
+        /// Error when forking a daemon.
+        DaemonFork {
+            description("Cannot fork daemon")
+        }
+
+        /// Error when redirecting a standard stream of a daemon.
+        DaemonRedirect(stream: i32) {
+            description("Cannot redirect standard stream of daemon")
+            display("Cannot redirect standard stream {} of daemon", stream)
+        }
+
+        /// Error when creating a new session for a daemon.
+        DaemonSetsid {
+            description("Cannot create new session for daemon")
+        }
+
+        /// Error caused by a daemon that failed to start up.
+        DaemonStartup(reason: String) {
+            description("Daemon failed to start up")
+            display("Daemon failed to start up: {}", reason)
+        }
+
+        /// Error when opening a file for the standard streams of a daemon.
+        DaemonStdio(path: PathBuf) {
+            description("Cannot open file for standard streams of daemon")
+            display("Cannot open file '{}' for the standard streams of the daemon", path.display())
         }
 
         /// Error when converting a domain into the TRust DNS format.
@@ -83,6 +172,17 @@ error_chain! {
             description("Cannot delete resource record")
         }
 
+        /// Error when the DNS server responds with an error.
+        DnsResponse(rcode: Rcode) {
+            description("DNS server responded with an error")
+            display("DNS server responded with '{}'", rcode)
+        }
+
+        /// Error when the DNS server does not respond in time.
+        DnsTimeout {
+            description("DNS server did not respond in time")
+        }
+
         /// Error when querying a resource record.
         DnsRecordQuery {
             description("Cannot query resource record")
@@ -94,9 +194,84 @@ error_chain! {
             display("Cannot update domain '{}' with IP address '{}'", domain, ip)
         }
 
-        /// Error caused by an invalid HTTP configuration.
-        HttpConfig {
-            description("Invalid HTTP configuration")
+        /// Error when binding a socket for the HTTP server.
+        HttpBind(socket: SocketAddr) {
+            description("Cannot bind socket")
+            display("Cannot bind socket '{}'", socket)
+        }
+
+        /// Error when binding a Unix domain socket for the HTTP server.
+        HttpBindUnix(path: PathBuf) {
+            description("Cannot bind Unix domain socket")
+            display("Cannot bind Unix domain socket '{}'", path.display())
+        }
+
+        /// Error when setting up the event loop of the HTTP server.
+        HttpReactor {
+            description("Cannot set up the event loop of the HTTP server")
+        }
+
+        /// Error when starting the HTTP server.
+        HttpServe(socket: String) {
+            description("Cannot start HTTP server")
+            display("Cannot start HTTP server at '{}'", socket)
+        }
+
+        /// Error when the thread running an embedded HTTP server panics.
+        HttpThread {
+            description("The thread of the HTTP server panicked")
+        }
+
+        /// Error when setting up a handler that shuts the server down when receiving a signal.
+        HttpSetupSignalHandler(signal: i32) {
+            description("Cannot setup signal handler")
+            display("Cannot setup a handler for signal {}", signal)
+        }
+
+        /// Error when opening a history file.
+        HistoryFileOpen(path: PathBuf) {
+            description("Cannot open history file")
+            display("Cannot open history file '{}'", path.display())
+        }
+
+        /// Error when reading a history file.
+        HistoryFileRead(path: PathBuf) {
+            description("Cannot read history file")
+            display("Cannot read history file '{}'", path.display())
+        }
+
+        /// Error when writing a history file.
+        HistoryFileWrite(path: PathBuf) {
+            description("Cannot write history file")
+            display("Cannot write history file '{}'", path.display())
+        }
+
+        /// Error when decoding a line of a history file.
+        HistoryFileDecode(path: PathBuf, line: usize) {
+            description("Cannot decode history file")
+            display("Cannot decode line {} of history file '{}'", line, path.display())
+        }
+
+        /// Error when setting up the logger.
+        LogInit {
+            description("Cannot set up the logger")
+        }
+
+        /// Error when the log output is a file, but there is no log file configured.
+        LogFileMissing {
+            description("There is no log file configured")
+        }
+
+        /// Error when opening a log file.
+        LogFileOpen(path: PathBuf) {
+            description("Cannot open log file")
+            display("Cannot open log file '{}'", path.display())
+        }
+
+        /// Error when connecting to the syslog daemon.
+        LogSyslogConnect(path: PathBuf) {
+            description("Cannot connect to the syslog daemon")
+            display("Cannot connect to the syslog daemon at '{}'", path.display())
         }
 
         /// Error when creating a lock file.
@@ -105,23 +280,217 @@ error_chain! {
             display("Cannot create lock file '{}'", path.display())
         }
 
+        /// Error when a lock file is held by a process other than `dynonym`.
+        LockFileForeign(path: PathBuf, pid: i32) {
+            description("Lock file is held by a foreign process")
+            display(
+                "Lock file '{}' is held by process {}, which is not dynonym",
+                path.display(),
+                pid,
+            )
+        }
+
+        /// Error when a lock file is locked by another process.
+        LockFileHeld(path: PathBuf, pid: Option<i32>) {
+            description("Lock file is held by another process")
+            display("Lock file '{}' is held by {}", path.display(), match *pid {
+                Some(pid) => format!("process {}", pid),
+                None => "another process".into(),
+            })
+        }
+
+        /// Error when locking a lock file.
+        LockFileLock(path: PathBuf) {
+            description("Cannot lock lock file")
+            display("Cannot lock lock file '{}'", path.display())
+        }
+
+        /// Error when checking which program holds a lock file.
+        LockFileProgram(path: PathBuf, pid: i32) {
+            description("Cannot check which program holds the lock file")
+            display(
+                "Cannot check whether process {} holding lock file '{}' is dynonym",
+                pid,
+                path.display(),
+            )
+        }
+
+        /// Error when reading a lock file.
+        LockFileRead(path: PathBuf) {
+            description("Cannot read lock file")
+            display("Cannot read lock file '{}'", path.display())
+        }
+
         /// Error when removing a lock file.
         LockFileRemove(path: PathBuf) {
             description("Cannot remove lock file")
             display("Cannot remove lock file '{}'", path.display())
         }
 
+        /// Error when a lock file is not held by any process.
+        LockFileUnheld(path: PathBuf) {
+            description("Lock file is not held by any process")
+            display("Lock file '{}' is not held by any process", path.display())
+        }
+
         /// Error when writing a lock file.
         LockFileWrite(path: PathBuf) {
             description("Cannot write lock file")
             display("Cannot write lock file '{}'", path.display())
         }
 
-        /// Error when creating a handler that removes the lock file when receiving a SIGINT.
-        LockFileSetupSigintHandler {
-            description(
-                "Cannot create a handler that removes the lock file when receiving a SIGINT")
+        /// Error when hashing a password, e.g. due to invalid parameters of the algorithm.
+        PasswordHash(reason: String) {
+            description("Cannot hash password")
+            display("Cannot hash password: {}", reason)
+        }
+
+        /// Error when handing a file over to the user and group the server runs as.
+        PrivilegesChown(path: PathBuf) {
+            description("Cannot change owner of file")
+            display("Cannot change the owner of file '{}'", path.display())
+        }
+
+        /// Error when changing the root directory.
+        PrivilegesChroot(path: PathBuf) {
+            description("Cannot change root directory")
+            display("Cannot change the root directory to '{}'", path.display())
+        }
+
+        /// Error when switching to a group.
+        PrivilegesGroup(gid: u32) {
+            description("Cannot switch group")
+            display("Cannot switch to group {}", gid)
+        }
+
+        /// Error caused by a user without a usable primary group if no group is configured.
+        PrivilegesMissingGroup(user: String) {
+            description("Group required for user")
+            display("The user '{}' has no primary group other than root, a group is required", user)
+        }
+
+        /// Error caused by a path outside of the new root directory.
+        PrivilegesOutside(path: PathBuf, root: PathBuf) {
+            description("Path is outside of root directory")
+            display(
+                "Path '{}' is outside of the root directory '{}'",
+                path.display(),
+                root.display(),
+            )
+        }
+
+        /// Error caused by an unknown group.
+        PrivilegesUnknownGroup(name: String) {
+            description("Unknown group")
+            display("Unknown group '{}'", name)
+        }
+
+        /// Error caused by an unknown user.
+        PrivilegesUnknownUser(name: String) {
+            description("Unknown user")
+            display("Unknown user '{}'", name)
+        }
+
+        /// Error caused by a file that is not readable after dropping privileges.
+        PrivilegesUnreadable(path: PathBuf) {
+            description("File is not readable after dropping privileges")
+            display("'{}' is not readable after dropping privileges", path.display())
+        }
+
+        /// Error caused by a file that is not writable after dropping privileges.
+        PrivilegesUnwritable(path: PathBuf) {
+            description("File is not writable after dropping privileges")
+            display("'{}' is not writable after dropping privileges", path.display())
+        }
+
+        /// Error when switching to a user.
+        PrivilegesUser(uid: u32) {
+            description("Cannot switch user")
+            display("Cannot switch to user {}", uid)
+        }
+
+        /// Error when sending a signal to a process.
+        ProcessSignal(pid: i32, signal: i32) {
+            description("Cannot send signal to process")
+            display("Cannot send signal {} to process {}", signal, pid)
+        }
+
+        /// Error when a process does not stop in time.
+        ProcessStopTimeout(pid: i32, timeout: u64) {
+            description("Process did not stop in time")
+            display("Process {} did not stop within {} seconds", pid, timeout)
+        }
+
+        /// Error when parsing a DNS response code.
+        RcodeParse(rcode: String) {
+            description("Cannot parse DNS response code")
+            display("Cannot parse DNS response code '{}'", rcode)
+        }
+
+        /// Error when generating a secret, e.g. due to an unavailable random number generator.
+        SecretGenerate(reason: String) {
+            description("Cannot generate secret")
+            display("Cannot generate secret: {}", reason)
+        }
+
+        /// Error caused by a period of validity that exceeds the range of expiry times.
+        SignValidity(valid: u64) {
+            description("Period of validity too long")
+            display("The period of validity of {} seconds is too long", valid)
+        }
+
+        /// Error caused by an invalid number of sockets passed by systemd.
+        SystemdListenFds {
+            description("Invalid number of sockets passed by systemd")
+        }
+
+        /// Error caused by a configured listener whose socket systemd did not pass.
+        SystemdListener(listener: String) {
+            description("Listener not passed by systemd")
+            display("The listener '{}' is configured, but not passed by systemd", listener)
+        }
+
+        /// Error when taking over a socket passed by systemd.
+        SystemdSocket(fd: i32) {
+            description("Cannot take over socket passed by systemd")
+            display("Cannot take over socket passed by systemd as file descriptor {}", fd)
+        }
+    }
+}
+
+impl Error {
+    /// Returns the DNS response code that caused the error, if any.
+    pub fn rcode(&self) -> Option<Rcode> {
+        self.find(|kind| match *kind {
+            ErrorKind::DnsResponse(rcode) => Some(rcode),
+            _ => None,
+        })
+    }
+
+    /// Returns whether the error was caused by a DNS server that did not respond in time.
+    pub fn is_timeout(&self) -> bool {
+        self.find(|kind| match *kind {
+            ErrorKind::DnsTimeout => Some(()),
+            _ => None,
+        }).is_some()
+    }
+
+    /// Describes the error including its causes, e.g. for a log message or a response body.
+    pub fn describe(&self) -> String {
+        self.iter().map(|err| err.to_string()).collect::<Vec<_>>().join(": ")
+    }
+
+    /// Applies the given function to the kind of this error and every causing error of this type
+    /// until it returns something.
+    fn find<T, F: Fn(&ErrorKind) -> Option<T>>(&self, f: F) -> Option<T> {
+        let mut next = Some(self);
+        while let Some(err) = next {
+            if let Some(found) = f(err.kind()) {
+                return Some(found);
+            }
+            next = err.1.next_error.as_ref().and_then(|err| err.downcast_ref::<Error>());
         }
+        None
     }
 }
 
@@ -0,0 +1,371 @@
+//! Update history
+//!
+//! The DNS server only knows the records it currently serves. A [`History`] keeps track of every
+//! update attempt instead: who tried to point which domain to which IP address, when and from
+//! where, and whether it worked. It is an append-only log file with one JSON-encoded [`Entry`] per
+//! line.
+//!
+//! A `History` also remembers the last successful update of every domain it has been asked for,
+//! so that the server can report it without reading the file again. The file is read backwards
+//! until the domain's last successful update shows up.
+//!
+//! A crash may leave a truncated line behind. Such a line is skipped when reading the file.
+//!
+//! [`History`]: struct.History.html
+//! [`Entry`]: struct.Entry.html
+
+use errors::*;
+use sign::now;
+use types::{Domain, Rcode};
+
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind as IoErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The effect of an update on a record.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Changed,
+    Unchanged,
+    Failed,
+    Rejected,
+}
+
+impl Effect {
+    /// Checks whether the record points to the IP address after the attempt.
+    pub fn is_success(self) -> bool {
+        match self {
+            Effect::Changed | Effect::Unchanged => true,
+            Effect::Failed | Effect::Rejected => false,
+        }
+    }
+}
+
+/// The number of bytes read at once when reading the history file backwards.
+const CHUNK: u64 = 8192;
+
+/// An update attempt.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// The time of the attempt (in seconds since the Unix epoch).
+    pub time: u64,
+
+    /// The user who requested the update.
+    pub user: String,
+
+    /// The IP address the update request came from.
+    pub source: IpAddr,
+
+    /// The domain to update.
+    pub domain: Domain,
+
+    /// The IP address the domain should point to.
+    pub ip: IpAddr,
+
+    /// The effect of the attempt.
+    pub result: Effect,
+
+    /// The DNS response code, if the attempt failed because of it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rcode: Option<Rcode>,
+
+    /// The reason why the attempt failed, if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Entry {
+    /// Creates a new entry for an attempt that happens right now.
+    pub fn new(user: &str, source: IpAddr, domain: Domain, ip: IpAddr, result: Effect) -> Self {
+        Entry {
+            time: now(),
+            user: user.into(),
+            source,
+            domain,
+            ip,
+            result,
+            rcode: None,
+            reason: None,
+        }
+    }
+}
+
+/// A filter for entries.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Only matches entries concerning this domain.
+    pub domain: Option<Domain>,
+
+    /// Only matches entries requested by this user.
+    pub user: Option<String>,
+
+    /// Only matches entries not older than this time (in seconds since the Unix epoch).
+    pub since: Option<u64>,
+}
+
+impl Filter {
+    /// Checks whether the given entry matches the filter.
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.domain.as_ref().map_or(true, |domain| *domain == entry.domain)
+            && self.user.as_ref().map_or(true, |user| *user == entry.user)
+            && self.since.map_or(true, |since| since <= entry.time)
+    }
+}
+
+/// An append-only log of update attempts.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    file: Mutex<File>,
+    last: Mutex<HashMap<Domain, Option<Entry>>>,
+}
+
+impl History {
+    /// Opens the history file at the given path, creating it if necessary.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        // Open the file for appending
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .chain_err(|| ErrorKind::HistoryFileOpen(path.clone()))?;
+
+        // Terminate a truncated last line, so that it does not spoil the next entry
+        let len = file.seek(SeekFrom::End(0))
+            .chain_err(|| ErrorKind::HistoryFileRead(path.clone()))?;
+        if len > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::End(-1))
+                .and_then(|_| file.read_exact(&mut last))
+                .chain_err(|| ErrorKind::HistoryFileRead(path.clone()))?;
+            if last[0] != b'\n' {
+                warn!("The last line of history file '{}' is truncated", path.display());
+                file.write_all(b"\n").chain_err(|| ErrorKind::HistoryFileWrite(path.clone()))?;
+            }
+        }
+
+        Ok(History {
+            path,
+            file: Mutex::new(file),
+            last: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the path of the history file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends the given entry.
+    pub fn append(&self, entry: Entry) -> Result<()> {
+        let mut line = serde_json::to_string(&entry)
+            .chain_err(|| ErrorKind::HistoryFileWrite(self.path.clone()))?;
+        line.push('\n');
+        self.file.lock().unwrap().write_all(line.as_bytes())
+            .chain_err(|| ErrorKind::HistoryFileWrite(self.path.clone()))?;
+
+        if entry.result.is_success() {
+            self.last.lock().unwrap().insert(entry.domain.clone(), Some(entry));
+        }
+        Ok(())
+    }
+
+    /// Returns the last successful update of the given domain, if any.
+    ///
+    /// Unless the domain has been asked for or updated before, the history file is read backwards
+    /// until the last successful update shows up. Errors are logged, as if there was no update.
+    pub fn last(&self, domain: &Domain) -> Option<Entry> {
+        if let Some(last) = self.last.lock().unwrap().get(domain) {
+            return last.clone();
+        }
+
+        // Note: An entry appended in the meantime is newer, thus it is kept.
+        match self.find(domain) {
+            Ok(found) => self.last.lock().unwrap().entry(domain.clone()).or_insert(found).clone(),
+            Err(err) => {
+                error!("Cannot find the last update of '{}': {}", domain, err.describe());
+                None
+            },
+        }
+    }
+
+    /// Finds the last successful update of the given domain by reading the history file
+    /// backwards.
+    fn find(&self, domain: &Domain) -> Result<Option<Entry>> {
+        let mut file = File::open(&self.path)
+            .chain_err(|| ErrorKind::HistoryFileOpen(self.path.clone()))?;
+        let mut pos = file.seek(SeekFrom::End(0))
+            .chain_err(|| ErrorKind::HistoryFileRead(self.path.clone()))?;
+
+        // Note: `rest` holds the bytes read but not yet decoded. Its first line may be incomplete
+        // until the beginning of the file has been read.
+        let mut rest = Vec::new();
+        loop {
+            while let Some(i) = rest.iter().rposition(|&byte| byte == b'\n') {
+                let line = rest.split_off(i + 1);
+                rest.truncate(i);
+                if let Some(entry) = self.decode(&line, domain) {
+                    return Ok(Some(entry));
+                }
+            }
+            if pos == 0 {
+                return Ok(self.decode(&rest, domain));
+            }
+
+            let len = pos.min(CHUNK);
+            pos -= len;
+            let mut chunk = vec![0; len as usize];
+            file.seek(SeekFrom::Start(pos))
+                .and_then(|_| file.read_exact(&mut chunk))
+                .chain_err(|| ErrorKind::HistoryFileRead(self.path.clone()))?;
+            chunk.extend_from_slice(&rest);
+            rest = chunk;
+        }
+    }
+
+    /// Decodes the given line of the history file, if it is a successful update of the given
+    /// domain. A malformed line is skipped with a warning.
+    fn decode(&self, line: &[u8], domain: &Domain) -> Option<Entry> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return None;
+        }
+        match serde_json::from_slice::<Entry>(line) {
+            Ok(ref entry) if entry.domain != *domain || !entry.result.is_success() => None,
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                warn!("Skipping a malformed line of '{}': {}", self.path.display(), err);
+                None
+            },
+        }
+    }
+
+    /// Reads all entries from the history file at the given path. A missing file is an empty
+    /// history.
+    ///
+    /// Every line is decoded separately, so that a malformed line, e.g. a line truncated by a
+    /// crash, does not hide the other entries.
+    pub fn read(path: &Path) -> Result<Vec<Result<Entry>>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).chain_err(|| ErrorKind::HistoryFileOpen(path.into()));
+            },
+        };
+
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.chain_err(|| ErrorKind::HistoryFileRead(path.into()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(
+                serde_json::from_str(&line)
+                    .chain_err(|| ErrorKind::HistoryFileDecode(path.into(), i + 1))
+            );
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn entry(user: &str, domain: &str, result: Effect) -> Entry {
+        Entry::new(
+            user,
+            "192.0.2.1".parse().unwrap(),
+            domain.parse().unwrap(),
+            "198.51.100.1".parse().unwrap(),
+            result,
+        )
+    }
+
+    #[test]
+    fn history_append_read() {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_owned();
+        let history = History::open(path.clone()).unwrap();
+        let mut failed = entry("tobias", "example.org", Effect::Failed);
+        failed.rcode = Some(Rcode::REFUSED);
+        failed.reason = Some("DNS server responded with 'REFUSED'".into());
+        history.append(entry("tobias", "example.org", Effect::Changed)).unwrap();
+        history.append(failed.clone()).unwrap();
+
+        let entries = History::read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(*entries[1].as_ref().unwrap(), failed);
+    }
+
+    #[test]
+    fn history_last() {
+        let file = NamedTempFile::new().unwrap();
+        let path = file.path().to_owned();
+        let domain = "example.org".parse().unwrap();
+        {
+            let history = History::open(path.clone()).unwrap();
+            assert_eq!(history.last(&domain), None);
+            history.append(entry("tobias", "example.org", Effect::Changed)).unwrap();
+            history.append(entry("sebastian", "example.org", Effect::Failed)).unwrap();
+            assert_eq!(history.last(&domain).unwrap().user, "tobias");
+        }
+
+        // Reopening restores the last successful update
+        let history = History::open(path).unwrap();
+        assert_eq!(history.last(&domain).unwrap().user, "tobias");
+    }
+
+    #[test]
+    fn history_truncated() {
+        let mut file = NamedTempFile::new().unwrap();
+        let path = file.path().to_owned();
+        let domain = "example.org".parse().unwrap();
+        let mut line = serde_json::to_string(&entry("tobias", "example.org", Effect::Changed))
+            .unwrap();
+        line.push('\n');
+        for _ in 0..1000 {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.write_all(&line.as_bytes()[..10]).unwrap();
+
+        // The truncated line is skipped and terminated
+        let history = History::open(path.clone()).unwrap();
+        assert_eq!(history.last(&domain).unwrap().user, "tobias");
+        history.append(entry("sebastian", "example.org", Effect::Rejected)).unwrap();
+        assert_eq!(history.last(&domain).unwrap().user, "tobias");
+
+        let entries = History::read(&path).unwrap();
+        assert_eq!(entries.len(), 1002);
+        assert!(entries[1000].is_err());
+        assert_eq!(entries[1001].as_ref().unwrap().result, Effect::Rejected);
+    }
+
+    #[test]
+    fn history_missing() {
+        // Note: The file is removed as soon as the handle is dropped.
+        let path = NamedTempFile::new().unwrap().path().to_owned();
+        assert!(History::read(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn filter_matches() {
+        let e = entry("tobias", "example.org", Effect::Changed);
+        assert!(Filter::default().matches(&e));
+        assert!(Filter { user: Some("tobias".into()), ..Filter::default() }.matches(&e));
+        assert!(!Filter { user: Some("sebastian".into()), ..Filter::default() }.matches(&e));
+        assert!(!Filter {
+            domain: Some("example.com".parse().unwrap()),
+            ..Filter::default()
+        }.matches(&e));
+        assert!(Filter { since: Some(e.time), ..Filter::default() }.matches(&e));
+        assert!(!Filter { since: Some(e.time + 1), ..Filter::default() }.matches(&e));
+    }
+}
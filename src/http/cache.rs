@@ -0,0 +1,134 @@
+//! Cache of verified credentials
+//!
+//! Verifying a password against its [`Hash`] is expensive by design. Clients that request updates
+//! frequently would keep the worker threads busy with hashing. A [`Cache`] remembers successful
+//! verifications for a short time instead. It never stores a password: Entries are keyed by an
+//! HMAC-SHA256 of the user's name and password, using a random key that never leaves the process.
+//!
+//! An entry is only valid as long as the user's password hash is unchanged, i.e. changing the
+//! password invalidates the cache for that user.
+//!
+//! [`Hash`]: ../../types/struct.Hash.html
+//! [`Cache`]: struct.Cache.html
+
+use types::Hash;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+/// A successful verification.
+#[derive(Debug)]
+struct Entry {
+    mac: Vec<u8>,
+    pw: Hash,
+    until: Instant,
+}
+
+/// A cache of successfully verified credentials.
+///
+/// # Example
+///
+/// ```
+/// use dynonym::http::cache::Cache;
+/// use dynonym::types::Hash;
+///
+/// let cache = Cache::new(60 /*sec*/);
+/// let hash: Hash = "s3cr3t".into();
+///
+/// assert!( cache.verify("tobias", "s3cr3t", &hash));  // slow: verifies the hash
+/// assert!( cache.verify("tobias", "s3cr3t", &hash));  // fast: cached
+/// assert!(!cache.verify("tobias", "wrong", &hash));
+/// ```
+#[derive(Debug)]
+pub struct Cache {
+    ttl: Duration,
+    key: [u8; 32],
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Cache {
+    /// Creates a new cache that remembers successful verifications for the given time (in
+    /// seconds). A time of `0` disables the cache.
+    pub fn new(ttl: u64) -> Self {
+        use rand::{OsRng, Rng};
+        let mut key = [0u8; 32];
+        OsRng::new().unwrap().fill_bytes(&mut key);
+        Cache {
+            ttl: Duration::from_secs(ttl),
+            key,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Verifies whether the given password matches the given user's password hash.
+    pub fn verify(&self, user: &str, pw: &str, hash: &Hash) -> bool {
+        if self.ttl == Duration::from_secs(0) {
+            return hash.is(pw);
+        }
+
+        // Look for a valid entry
+        let now = Instant::now();
+        let mac = self.mac(user, pw);
+        if let Some(entry) = self.entries.lock().unwrap().get(user) {
+            if entry.until > now && entry.pw == *hash && bool::from(entry.mac.ct_eq(&mac)) {
+                return true;
+            }
+        }
+
+        // Verify the password (without holding the lock) and remember the success
+        if !hash.is(pw) {
+            return false;
+        }
+        self.entries.lock().unwrap().insert(user.into(), Entry {
+            mac,
+            pw: hash.clone(),
+            until: now + self.ttl,
+        });
+        true
+    }
+
+    fn mac(&self, user: &str, pw: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(user.as_bytes());
+        mac.update(&[0]);
+        mac.update(pw.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_wrong_pw() {
+        let cache = Cache::new(60);
+        let hash: Hash = "1234".into();
+        assert!(cache.verify("tobias", "1234", &hash));
+        assert!(!cache.verify("tobias", "4321", &hash));
+        assert!(!cache.verify("sebastian", "4321", &hash));
+    }
+
+    #[test]
+    fn cache_pw_changed() {
+        let cache = Cache::new(60);
+        let old: Hash = "1234".into();
+        let new: Hash = "4321".into();
+        assert!(cache.verify("tobias", "1234", &old));
+        assert!(!cache.verify("tobias", "1234", &new));
+        assert!(cache.verify("tobias", "4321", &new));
+    }
+
+    #[test]
+    fn cache_disabled() {
+        let cache = Cache::new(0);
+        let hash: Hash = "1234".into();
+        assert!(cache.verify("tobias", "1234", &hash));
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+}
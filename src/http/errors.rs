@@ -1,40 +1,88 @@
-use rocket::{Request, Response};
-use rocket::http::Status;
-use rocket::response::Responder;
-use rocket::response::status::Custom;
-
-#[error(400)]
-pub fn bad_request() -> &'static str {
-    "400 Bad Request"
-}
+use http::response::{text, Responder};
+
+use hyper::StatusCode;
+use hyper::server::Response;
+use std::time::Duration;
 
-#[error(401)]
-pub fn unauthorized<'r>(req: &Request) -> Response<'r> {
-    Response::build_from(
-        Custom(Status::Unauthorized, "401 Unauthorized")
-            .respond_to(req)
-            .unwrap()
-    )
-    .raw_header("WWW-Authenticate", "Basic realm=\"dynonym\"")
-    .finalize()
+/// A failed request, optionally explaining why it failed.
+///
+/// Without a reason, a `Failure` is handled by the error catcher, see [`catch`]. With a reason,
+/// the response body is the status followed by that reason and any additional header is included.
+///
+/// [`catch`]: fn.catch.html
+#[derive(Debug)]
+pub struct Failure {
+    status: StatusCode,
+    reason: Option<String>,
+    headers: Vec<(&'static str, String)>,
 }
 
-#[error(403)]
-pub fn forbidden() -> &'static str {
-    "403 Forbidden"
+impl Failure {
+    /// Creates a new `Failure` with the given status.
+    pub fn new(status: StatusCode) -> Self {
+        Failure { status, reason: None, headers: Vec::new() }
+    }
+
+    /// Explains why the request failed.
+    pub fn with_reason<S: Into<String>>(mut self, reason: S) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Adds a header to the response. (Only takes effect if there is a reason.)
+    pub fn with_header<S: Into<String>>(mut self, name: &'static str, value: S) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// Returns the status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Returns the reason why the request failed, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_ref().map(String::as_str)
+    }
+
+    /// Adds a `Retry-After` header to the response given the time to wait (rounded up to whole
+    /// seconds).
+    pub fn retry_after(self, wait: Duration) -> Self {
+        let secs = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+        self.with_header("Retry-After", secs.to_string())
+    }
 }
 
-#[error(404)]
-pub fn not_found() -> &'static str {
-    "404 Not Found"
+impl From<StatusCode> for Failure {
+    fn from(status: StatusCode) -> Self {
+        Failure::new(status)
+    }
 }
 
-#[error(500)]
-pub fn internal_server_error() -> &'static str {
-    "500 Internal Server Error"
+impl Responder for Failure {
+    fn respond(self) -> Response {
+        match self.reason {
+            None => catch(self.status),
+            Some(reason) => {
+                let mut response = text(format!("{}: {}", self.status, reason))
+                    .with_status(self.status);
+                for (name, value) in self.headers {
+                    response.headers_mut().set_raw(name, value);
+                }
+                response
+            },
+        }
+    }
 }
 
-#[error(501)]
-pub fn not_implemented() -> &'static str {
-    "501 Not Implemented"
+/// The error catcher, i.e. the response to a failed request that does not explain why it failed.
+///
+/// The response body is merely the status, e.g. `404 Not Found`. A `401 Unauthorized` response
+/// asks for HTTP basic authentication.
+pub fn catch(status: StatusCode) -> Response {
+    let mut response = text(status.to_string()).with_status(status);
+    if status == StatusCode::Unauthorized {
+        response.headers_mut().set_raw("WWW-Authenticate", "Basic realm=\"dynonym\"");
+    }
+    response
 }
@@ -0,0 +1,67 @@
+//! Hooks
+//!
+//! A hook is a callback that is notified about every security-relevant [`Event`], e.g. a login or
+//! an update, right before it is recorded in the audit log. Hooks are notified even if the audit
+//! log is disabled. This way, an application embedding the server may react to updates.
+//!
+//! A hook is called on the event loop or within the thread pool, hence it must not block.
+//!
+//! [`Event`]: ../../audit/enum.Event.html
+
+use audit::Event;
+
+use std::fmt::{self, Debug, Formatter};
+
+/// A hook, i.e. a callback notified about an event.
+pub type Hook = Box<Fn(&Event) + Send + Sync>;
+
+/// A set of hooks.
+#[derive(Default)]
+pub struct Hooks(Vec<Hook>);
+
+impl Hooks {
+    /// Creates an empty set of hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given hook.
+    pub fn add<F>(&mut self, hook: F)
+        where F: Fn(&Event) + Send + Sync + 'static
+    {
+        self.0.push(Box::new(hook));
+    }
+
+    /// Notifies every hook about the given event.
+    pub fn notify(&self, event: &Event) {
+        for hook in &self.0 {
+            hook(event);
+        }
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn hooks_notify() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut hooks = Hooks::new();
+        for _ in 0..2 {
+            let count = count.clone();
+            hooks.add(move |_| { count.fetch_add(1, Ordering::SeqCst); });
+        }
+
+        hooks.notify(&Event::Config { uid: 0, command: "configure".into() });
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
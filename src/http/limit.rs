@@ -0,0 +1,204 @@
+//! Rate limiting of updates
+//!
+//! A misbehaving client may request updates in a loop, hammering the DNS server. A [`Limiter`]
+//! prevents this using token buckets: There is a bucket per user and a bucket per domain. Every
+//! update takes a token from both buckets. Tokens are refilled at a constant rate up to the
+//! bucket's capacity. While either bucket is empty, updates are refused.
+//!
+//! [`Limiter`]: struct.Limiter.html
+
+use config::{Limit, Limits};
+use types::Domain;
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Something that is rate limited.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Subject {
+    User(String),
+    Domain(Domain),
+}
+
+impl Display for Subject {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Subject::User(ref user) => write!(f, "user '{}'", user),
+            Subject::Domain(ref domain) => write!(f, "domain '{}'", domain),
+        }
+    }
+}
+
+/// A token bucket.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refills the bucket according to the time passed since the last refill.
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        let tokens = if limit.interval == 0 {
+            f64::from(limit.burst)
+        } else {
+            self.tokens + elapsed / limit.interval as f64
+        };
+        self.tokens = tokens.min(f64::from(limit.burst));
+        self.updated = now;
+    }
+
+    /// Computes the time until the given number of tokens is available.
+    fn wait(&self, limit: Limit, tokens: f64) -> Duration {
+        let secs = (tokens - self.tokens) * limit.interval as f64;
+        Duration::from_secs(secs.ceil() as u64)
+    }
+}
+
+/// A rate limiter for updates.
+#[derive(Debug)]
+pub struct Limiter {
+    config: Limits,
+    buckets: Mutex<HashMap<Subject, Bucket>>,
+}
+
+impl Limiter {
+    /// Creates a new limiter given the configuration.
+    pub fn new(config: Limits) -> Self {
+        Limiter {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for an update of the given domain by the given user.
+    ///
+    /// If either bucket is empty, no token is taken at all and the time until the update may be
+    /// retried is returned.
+    pub fn take(&self, user: &str, domain: &Domain) -> Result<(), Duration> {
+        self.take_all(user, &[domain])
+    }
+
+    /// Takes the tokens for updates of all the given domains by the given user, i.e. a token per
+    /// domain from the user's bucket and from the respective domain's bucket.
+    ///
+    /// This is all or nothing: If any bucket lacks a token, no token is taken at all and the time
+    /// until the updates may be retried is returned.
+    pub fn take_all(&self, user: &str, domains: &[&Domain]) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        // Count the tokens needed per bucket
+        let mut demands = vec![(Subject::User(user.into()), self.config.user, 0u32)];
+        for &domain in domains {
+            demands[0].2 += 1;
+            let subject = Subject::Domain(domain.clone());
+            match demands.iter().position(|&(ref other, _, _)| *other == subject) {
+                Some(index) => demands[index].2 += 1,
+                None => demands.push((subject, self.config.domain, 1)),
+            }
+        }
+        demands.retain(|&(_, limit, tokens)| limit.burst > 0 && tokens > 0);
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // Refill the buckets and find out whether there are enough tokens
+        let mut wait = None;
+        for &(ref subject, limit, tokens) in &demands {
+            let tokens = f64::from(tokens);
+            let bucket = buckets.entry(subject.clone()).or_insert(Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+            });
+            bucket.refill(limit, now);
+            if bucket.tokens < tokens {
+                let bucket_wait = bucket.wait(limit, tokens);
+                warn!(
+                    "Rate limit exceeded for {}, retry in {} seconds",
+                    subject,
+                    bucket_wait.as_secs(),
+                );
+                wait = wait.max(Some(bucket_wait));
+            }
+        }
+        if let Some(wait) = wait {
+            return Err(wait);
+        }
+
+        // Take the tokens
+        for &(ref subject, limit, tokens) in &demands {
+            let bucket = buckets.get_mut(subject).unwrap();
+            bucket.tokens -= f64::from(tokens);
+            info!(
+                "Rate limit for {}: {:.1} of {} tokens left",
+                subject,
+                bucket.tokens,
+                limit.burst,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(user: u32, domain: u32) -> Limiter {
+        Limiter::new(Limits {
+            user: Limit { burst: user, interval: 3600 },
+            domain: Limit { burst: domain, interval: 3600 },
+        })
+    }
+
+    #[test]
+    fn limit_user() {
+        let limiter = limiter(2, 0);
+        let domain = "example.org".parse().unwrap();
+        assert!(limiter.take("tobias", &domain).is_ok());
+        assert!(limiter.take("tobias", &"example.com".parse().unwrap()).is_ok());
+        assert!(limiter.take("tobias", &"example.net".parse().unwrap()).is_err());
+        assert!(limiter.take("sebastian", &domain).is_ok());
+    }
+
+    #[test]
+    fn limit_domain() {
+        let limiter = limiter(0, 1);
+        let domain = "example.org".parse().unwrap();
+        assert!(limiter.take("tobias", &domain).is_ok());
+        assert!(limiter.take("sebastian", &domain).is_err());
+        assert!(limiter.take("sebastian", &"example.com".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn limit_atomic() {
+        let limiter = limiter(2, 1);
+        let domain = "example.org".parse().unwrap();
+        assert!(limiter.take("tobias", &domain).is_ok());
+
+        // The domain's bucket is empty, so the user's token must not be taken
+        let wait = limiter.take("tobias", &domain).unwrap_err();
+        assert!(wait > Duration::from_secs(3500));
+        assert!(limiter.take("tobias", &"example.com".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn limit_all() {
+        let limiter = limiter(3, 1);
+        let org = "example.org".parse().unwrap();
+        let com = "example.com".parse().unwrap();
+        let net = "example.net".parse().unwrap();
+
+        // The same domain twice needs two tokens from its bucket
+        assert!(limiter.take_all("tobias", &[&org, &org]).is_err());
+        assert!(limiter.take_all("tobias", &[&org, &com]).is_ok());
+
+        // The user's bucket holds a single token, so nothing must be taken
+        assert!(limiter.take_all("tobias", &[&net, &"example.edu".parse().unwrap()]).is_err());
+        assert!(limiter.take("tobias", &net).is_ok());
+    }
+}
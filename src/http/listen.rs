@@ -0,0 +1,367 @@
+//! Listeners
+//!
+//! The HTTP server listens on its socket address and on any number of additional listeners, i.e.
+//! TCP socket addresses or Unix domain sockets. A [`Socket`] is a bound listener. Every socket is
+//! served on the same event loop and all of them share a single [`Router`].
+//!
+//! Connections via a Unix domain socket have no client address. Since such a socket is meant for a
+//! reverse proxy on the same host, the client address is taken from a header set by the reverse
+//! proxy instead, by default from the last address within `X-Forwarded-For`, see [`forwarded`]. A
+//! request without that header is refused, rather than sharing a made-up client address with
+//! every other such request.
+//!
+//! [`Socket`]: enum.Socket.html
+//! [`Router`]: ../service/struct.Router.html
+//! [`forwarded`]: fn.forwarded.html
+
+use config::Listener;
+use errors::*;
+use http::privileges::{chown, lookup_group, lookup_user};
+use http::service::{Router, Service};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper::Headers;
+use hyper::server::Http;
+use libc;
+use mio::{self, PollOpt, Ready, Token};
+use mio::event::Evented;
+use mio::unix::EventedFd;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, Permissions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str;
+use std::time::Duration;
+use tokio_core::net;
+use tokio_core::reactor::{Handle, Interval, PollEvented};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The interval (in milliseconds) at which a socket checks whether the server is stopping.
+const STOPPING_INTERVAL: u64 = 100;
+
+/// A socket the HTTP server listens on.
+#[derive(Debug)]
+pub enum Socket {
+    /// A TCP socket.
+    Tcp(TcpListener),
+
+    /// A Unix domain socket, the path of its socket file and the header the reverse proxy passes
+    /// the client's address in, if not the default one.
+    Unix(UnixListener, PathBuf, Option<String>),
+}
+
+impl Socket {
+    /// Binds the given listener.
+    pub fn bind(listener: &Listener) -> Result<Self> {
+        match *listener {
+            Listener::Tcp { socket } => {
+                let tcp = TcpListener::bind(socket).chain_err(|| ErrorKind::HttpBind(socket))?;
+                Ok(Socket::Tcp(tcp))
+            },
+            Listener::Unix { ref path, ref owner, ref group, ref header, .. } => {
+                let unix = bind_unix(path, listener.mode()?, owner.as_ref(), group.as_ref())
+                    .chain_err(|| ErrorKind::HttpBindUnix(path.clone()))?;
+                Ok(Socket::Unix(unix, path.clone(), header.clone()))
+            },
+        }
+    }
+
+    /// Checks whether this socket is bound as the given listener demands, e.g. a socket passed by
+    /// systemd.
+    pub fn matches(&self, listener: &Listener) -> bool {
+        match (self, listener) {
+            (&Socket::Tcp(ref tcp), &Listener::Tcp { socket }) => {
+                tcp.local_addr().ok() == Some(socket)
+            },
+            (&Socket::Unix(_, ref path, _), &Listener::Unix { path: ref other, .. }) => {
+                path == other
+            },
+            _ => false,
+        }
+    }
+
+    /// Applies the settings of the given listener that do not concern binding, i.e. the header
+    /// of a Unix domain socket.
+    pub fn configure(self, listener: &Listener) -> Self {
+        match (self, listener) {
+            (Socket::Unix(unix, path, _), &Listener::Unix { ref header, .. }) => {
+                Socket::Unix(unix, path, header.clone())
+            },
+            (socket, _) => socket,
+        }
+    }
+
+    /// Serves the routes of the given router at this socket on the event loop of the given
+    /// handle.
+    ///
+    /// The server runs as soon as the event loop runs.
+    pub fn serve(self, router: Rc<Router>, handle: &Handle) -> Result<()> {
+        let name = self.to_string();
+        info!("Listening on {}", name);
+
+        match self {
+            Socket::Tcp(tcp) => {
+                let incoming = tcp.local_addr()
+                    .and_then(|socket| net::TcpListener::from_listener(tcp, &socket, handle))
+                    .chain_err(|| ErrorKind::HttpServe(name.clone()))?
+                    .incoming()
+                    .map(|(stream, peer)| (stream, Peer::Tcp(peer)));
+                accept(incoming, name, router, handle)?;
+            },
+            Socket::Unix(unix, _, header) => {
+                let incoming = unix.set_nonblocking(true)
+                    .and_then(|_| PollEvented::new(Fd(unix), handle))
+                    .chain_err(|| ErrorKind::HttpServe(name.clone()))?;
+                let incoming = UnixIncoming { listener: incoming, handle: handle.clone() }
+                    .map(move |stream| (stream, Peer::Proxy(header.clone())));
+                accept(incoming, name, router, handle)?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Display for Socket {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Socket::Tcp(ref tcp) => match tcp.local_addr() {
+                Ok(socket) => write!(f, "{}", socket),
+                Err(_) => write!(f, "<unknown>"),
+            },
+            Socket::Unix(_, ref path, _) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The peer of a connection.
+#[derive(Clone, Debug)]
+pub enum Peer {
+    /// A client connected via TCP from the given address.
+    Tcp(SocketAddr),
+
+    /// A reverse proxy connected via a Unix domain socket. It passes the client's address in the
+    /// given header, if not in the default one.
+    Proxy(Option<String>),
+}
+
+/// Returns the client address of a request via a Unix domain socket, as set by a reverse proxy
+/// in the given header or, by default, in the `X-Forwarded-For` header.
+///
+/// Within `X-Forwarded-For`, only the last address is taken. It was added by the reverse proxy,
+/// whereas any other address may have been made up by the client.
+pub fn forwarded(headers: &Headers, header: Option<&str>) -> Option<SocketAddr> {
+    let value = headers
+        .get_raw(header.unwrap_or("X-Forwarded-For"))
+        .and_then(|raw| raw.iter().last())
+        .and_then(|value| str::from_utf8(value).ok());
+    client_ip(value).map(|ip| SocketAddr::new(ip, 0))
+}
+
+/// Determines the client's IP address given the value of the last header passing it, i.e. the
+/// last address within the value.
+fn client_ip(value: Option<&str>) -> Option<IpAddr> {
+    value
+        .and_then(|list| list.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+}
+
+/// Returns the client address of connections via a Unix domain socket without a client address,
+/// e.g. for logging.
+pub fn unix_peer() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0)
+}
+
+/// Binds a Unix domain socket at the given path and adjusts the permissions of its socket file.
+fn bind_unix(path: &Path, mode: Option<u32>, owner: Option<&String>, group: Option<&String>)
+    -> Result<UnixListener>
+{
+    // Remove a stale socket file, but never one that is still in use
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() || UnixStream::connect(path).is_ok() {
+            bail!(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        fs::remove_file(path)?;
+    }
+
+    // Bind the socket and adjust the permissions
+    // Note: The socket file is created with restrictive permissions right away, so that no other
+    // user can connect before they are adjusted. Without a configured mode, the umask applies.
+    let umask = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    let unix = bound?;
+    if owner.is_some() || group.is_some() {
+        let uid = match owner {
+            Some(name) => lookup_user(name)?.0,
+            None => !0,
+        };
+        let gid = match group {
+            Some(name) => lookup_group(name)?,
+            None => !0,
+        };
+        chown(path, uid, gid)?;
+    }
+    let mode = mode.unwrap_or(0o777 & !u32::from(umask));
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    Ok(unix)
+}
+
+/// Serves every connection accepted from the given stream on the event loop of the given handle.
+/// A connection comes with its peer. As soon as the server is stopping, the stream is dropped, i.e.
+/// the socket stops accepting connections.
+fn accept<S, I>(incoming: S, name: String, router: Rc<Router>, handle: &Handle) -> Result<()>
+    where S: Stream<Item = (I, Peer), Error = io::Error> + 'static,
+          I: AsyncRead + AsyncWrite + 'static,
+{
+    let http: Http = Http::new();
+    let spawner = handle.clone();
+    let shutdown = router.context().shutdown.clone();
+
+    // Note: Without connections, the stream is only checked for a shutdown on a tick.
+    let ticks = Interval::new(Duration::from_millis(STOPPING_INTERVAL), handle)
+        .chain_err(|| ErrorKind::HttpReactor)?
+        .map(|_| None);
+    let connections = incoming
+        .then(move |result| {
+            // Note: A failed connection must not stop the server from accepting others.
+            if let Err(ref err) = result {
+                warn!("Cannot accept a connection on {}: {}", name, err);
+            }
+            Ok(result.ok())
+        })
+        .select(ticks.then(|result| Ok::<_, io::Error>(result.unwrap_or(None))))
+        .take_while(move |_| Ok(!shutdown.is_stopping()))
+        .filter_map(|connection| connection)
+        .map_err(|_| ())
+        .for_each(move |(io, peer)| {
+            let service = Service::new(router.clone(), peer, spawner.clone());
+            let served = http.serve_connection(io, service)
+                .map(|_| ())
+                .map_err(|err| debug!("Connection failed: {}", err));
+            spawner.spawn(served);
+            Ok(())
+        });
+    handle.spawn(connections);
+    Ok(())
+}
+
+/// A file descriptor registered with the event loop, e.g. a Unix domain socket.
+#[derive(Debug)]
+struct Fd<T>(T);
+
+impl<T: AsRawFd> Evented for Fd<T> {
+    fn register(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt)
+        -> io::Result<()>
+    {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt)
+        -> io::Result<()>
+    {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+impl Read for Fd<UnixStream> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Fd<UnixStream> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// The stream of connections accepted at a Unix domain socket.
+struct UnixIncoming {
+    listener: PollEvented<Fd<UnixListener>>,
+    handle: Handle,
+}
+
+impl Stream for UnixIncoming {
+    type Item = PollEvented<Fd<UnixStream>>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, io::Error> {
+        if let Async::NotReady = self.listener.poll_read() {
+            return Ok(Async::NotReady);
+        }
+        match self.listener.get_ref().0.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true)?;
+                Ok(Async::Ready(Some(PollEvented::new(Fd(stream), &self.handle)?)))
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                self.listener.need_read();
+                Ok(Async::NotReady)
+            },
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn client_ip_header() {
+        let ip = "192.0.2.1".parse().ok();
+        assert_eq!(client_ip(Some("198.51.100.1, 192.0.2.1")), ip);
+        assert_eq!(client_ip(Some("192.0.2.1")), ip);
+        assert_eq!(client_ip(Some("unknown")), None);
+        assert_eq!(client_ip(None), None);
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let mut headers = Headers::new();
+        headers.set_raw("X-Real-IP", "203.0.113.1");
+        headers.set_raw("X-Forwarded-For", "203.0.113.1, 192.0.2.1");
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(forwarded(&headers, None).unwrap().ip(), ip);
+        let ip: IpAddr = "203.0.113.1".parse().unwrap();
+        assert_eq!(forwarded(&headers, Some("X-Real-IP")).unwrap().ip(), ip);
+        assert_eq!(forwarded(&headers, Some("X-Client-IP")), None);
+    }
+
+    #[test]
+    fn socket_unix() {
+        let path = NamedTempFile::new().unwrap().path().to_owned();
+        let listener = Listener::Unix {
+            path: path.clone(),
+            mode: Some("600".into()),
+            owner: None,
+            group: None,
+            header: None,
+        };
+
+        // Note: A socket file in use must not be replaced.
+        let socket = Socket::bind(&listener).unwrap();
+        assert_eq!(socket.to_string(), format!("unix:{}", path.display()));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(Socket::bind(&listener).is_err());
+
+        // Note: A stale socket file is replaced.
+        drop(socket);
+        Socket::bind(&listener).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
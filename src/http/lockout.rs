@@ -0,0 +1,232 @@
+//! Brute-force protection
+//!
+//! Verifying credentials is expensive by design. In order to prevent attackers from guessing
+//! passwords (and from burning CPU time while doing so), a [`Tracker`] counts failed logins per
+//! user and per client. As soon as either one fails too often within the configured time window,
+//! it is locked out for a while. Credentials are not even verified during a lockout.
+//!
+//! Every failed login is answered after a delay. The delay is applied by a timer on the event loop,
+//! so that it neither blocks the thread pool nor the event loop.
+//!
+//! [`Tracker`]: struct.Tracker.html
+
+use config::Lockout;
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Something that can be locked out.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum Subject {
+    User(String),
+    Client(IpAddr),
+}
+
+impl Display for Subject {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Subject::User(ref user) => write!(f, "user '{}'", user),
+            Subject::Client(ref ip) => write!(f, "client '{}'", ip),
+        }
+    }
+}
+
+/// The failed logins of a subject within the current time window.
+#[derive(Debug)]
+struct Entry {
+    failures: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// A tracker for failed logins.
+#[derive(Debug)]
+pub struct Tracker {
+    config: Lockout,
+    entries: Mutex<HashMap<Subject, Entry>>,
+}
+
+impl Tracker {
+    /// Creates a new tracker given the configuration.
+    pub fn new(config: Lockout) -> Self {
+        Tracker {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks whether the given user or client is locked out. If so, the remaining time of the
+    /// lockout is returned.
+    pub fn check(&self, user: &str, client: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        [Subject::User(user.into()), Subject::Client(client)]
+            .iter()
+            .filter_map(|subject| entries.get(subject))
+            .filter_map(|entry| entry.locked_until)
+            .filter(|&until| until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// Records a failed login of the given user from the given client.
+    ///
+    /// The caller is supposed to answer the failed login after the [`delay`].
+    ///
+    /// [`delay`]: #method.delay
+    pub fn fail(&self, user: &str, client: IpAddr) {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window);
+        let mut entries = self.entries.lock().unwrap();
+
+        // Count the failure for both, the user and the client
+        for subject in vec![Subject::User(user.into()), Subject::Client(client)] {
+            let entry = entries.entry(subject.clone()).or_insert(Entry {
+                failures: 0,
+                since: now,
+                locked_until: None,
+            });
+
+            // Start a new time window, if necessary
+            if now.duration_since(entry.since) >= window {
+                entry.failures = 0;
+                entry.since = now;
+            }
+            entry.failures += 1;
+
+            // Lock out, if necessary
+            let locked = entry.locked_until.map_or(false, |until| until > now);
+            if self.config.attempts > 0 && entry.failures >= self.config.attempts && !locked {
+                entry.locked_until = Some(now + Duration::from_secs(self.config.duration));
+                warn!(
+                    "Locking out {} for {} seconds after {} failed logins",
+                    subject,
+                    self.config.duration,
+                    entry.failures,
+                );
+            }
+        }
+    }
+
+    /// Returns the time a failed login is delayed.
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.config.delay)
+    }
+
+    /// Returns the time between two calls of [`prune`].
+    ///
+    /// [`prune`]: #method.prune
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.config.window.max(1))
+    }
+
+    /// Forgets about the subjects that neither failed recently nor are locked out.
+    ///
+    /// This keeps the tracker from growing without bounds. It is supposed to be called
+    /// periodically, e.g. by a timer on the event loop.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window);
+        self.entries.lock().unwrap().retain(|_, entry| {
+            now.duration_since(entry.since) < window
+                || entry.locked_until.map_or(false, |until| until > now)
+        });
+    }
+
+    /// Records a successful login of the given user. This resets the user's failed logins.
+    pub fn succeed(&self, user: &str) {
+        self.entries.lock().unwrap().remove(&Subject::User(user.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> Tracker {
+        Tracker::new(Lockout {
+            attempts: 3,
+            window: 60,
+            duration: 60,
+            delay: 0,
+        })
+    }
+
+    #[test]
+    fn lockout_user() {
+        let tracker = tracker();
+        let ip = "192.0.2.1".parse().unwrap();
+        for _ in 0..2 {
+            tracker.fail("tobias", ip);
+            assert!(tracker.check("tobias", ip).is_none());
+        }
+        tracker.fail("tobias", ip);
+        assert!(tracker.check("tobias", ip).is_some());
+        assert!(tracker.check("tobias", "192.0.2.2".parse().unwrap()).is_some());
+        assert!(tracker.check("sebastian", ip).is_some());
+        assert!(tracker.check("sebastian", "192.0.2.2".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn lockout_client() {
+        let tracker = tracker();
+        let ip = "192.0.2.1".parse().unwrap();
+        for user in &["a", "b", "c"] {
+            tracker.fail(user, ip);
+        }
+        assert!(tracker.check("d", ip).is_some());
+        assert!(tracker.check("d", "192.0.2.2".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn lockout_reset() {
+        let tracker = tracker();
+        let ip1 = "192.0.2.1".parse().unwrap();
+        let ip2 = "192.0.2.2".parse().unwrap();
+        tracker.fail("tobias", ip1);
+        tracker.fail("tobias", ip2);
+        tracker.succeed("tobias");
+        tracker.fail("tobias", "192.0.2.3".parse().unwrap());
+        assert!(tracker.check("tobias", "192.0.2.4".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn lockout_prune() {
+        let stale = Tracker::new(Lockout {
+            attempts: 3,
+            window: 0,
+            duration: 60,
+            delay: 0,
+        });
+        let ip = "192.0.2.1".parse().unwrap();
+        for _ in 0..3 {
+            stale.fail("tobias", ip);
+        }
+        stale.fail("sebastian", "192.0.2.2".parse().unwrap());
+        stale.prune();
+        assert_eq!(stale.entries.lock().unwrap().len(), 0);
+
+        let recent = tracker();
+        recent.fail("tobias", ip);
+        recent.prune();
+        assert_eq!(recent.entries.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn lockout_disabled() {
+        let tracker = Tracker::new(Lockout {
+            attempts: 0,
+            window: 60,
+            duration: 60,
+            delay: 0,
+        });
+        let ip = "192.0.2.1".parse().unwrap();
+        for _ in 0..10 {
+            tracker.fail("tobias", ip);
+        }
+        assert!(tracker.check("tobias", ip).is_none());
+    }
+}
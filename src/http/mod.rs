@@ -1,44 +1,604 @@
 //! Web server (incl. routes)
+//!
+//! `dynonym serve` runs the server via [`serve`], which blocks until the process receives a signal.
+//! An application embedding `dynonym` assembles a [`Server`] instead: Besides the configuration, it
+//! may pass a DNS [`Backend`], additional [`Route`]s and hooks. The server runs in a background
+//! thread until its [`Handle`] shuts it down.
+//!
+//! ```no_run
+//! # extern crate dynonym;
+//! # extern crate hyper;
+//! use dynonym::config::Config;
+//! use dynonym::http::Server;
+//! use dynonym::http::service::{answer, Route};
+//! use hyper::Method;
+//!
+//! # fn main() {
+//! let mut config = Config::default();
+//! config.http.socket = "127.0.0.1:0".parse().unwrap();
+//!
+//! let server = Server::new(config)
+//!     .route(Route::new(Method::Get, "/version", |_, _| answer("1.0")))
+//!     .hook(|event| println!("{:?}", event))
+//!     .start()
+//!     .unwrap();
+//! println!("Listening on {}", server.addr());
+//! server.shutdown().unwrap();
+//! # }
+//! ```
+//!
+//! [`serve`]: fn.serve.html
+//! [`Server`]: struct.Server.html
+//! [`Backend`]: ../dns/trait.Backend.html
+//! [`Route`]: service/struct.Route.html
+//! [`Handle`]: struct.Handle.html
 
+pub mod cache;
 pub mod errors;
+pub mod hooks;
+pub mod limit;
+pub mod listen;
+pub mod lockout;
+pub mod privileges;
+pub mod probe;
+pub mod rehash;
+pub mod reload;
+pub mod request;
+pub mod response;
 pub mod routes;
+pub mod service;
+pub mod shutdown;
+pub mod systemd;
 
-use config::Config;
+use audit::{Event, Trail};
+use config::{Config, Listener, Metrics, Shared};
+use dns::{Backend, Rfc2136};
 use errors::*;
+use history::History;
+use http::hooks::Hooks;
+use http::listen::Socket;
+use http::service::{Context, Route, Router};
+use http::shutdown::Shutdown;
+use lock::Lock;
+use metrics::Registry;
 
-use rocket::config::Environment;
-
-pub fn serve(config: Config) -> Result<()> {
-    // TODO Remove as soon as the errors![] macro bugfix was included in Rocket!
-    use rocket;
-
-    // Assemble the Rocket configuration
-    let rocket_config = ::rocket::Config
-        ::build(Environment::Development)
-        .address(format!("{}", config.http.socket.ip()))
-        .port(config.http.socket.port())
-        .workers(config.http.workers)
-        .finalize()
-        .chain_err(|| ErrorKind::HttpConfig)?;
-
-    // Configure the HTTP server and start it
-    ::rocket::custom(rocket_config, true)
-        .mount("/", routes![
-            routes::dns::update,
-            routes::ip,
-            routes::port,
-            routes::socket,
-        ])
-        .catch(errors![
-            errors::bad_request,
-            errors::unauthorized,
-            errors::forbidden,
-            errors::not_found,
-            errors::internal_server_error,
-            errors::not_implemented,
-        ])
-        .manage(config)
-        .launch();
-
-    Ok(())
+use futures::{future, Stream};
+use futures::sync::oneshot;
+use futures_cpupool::CpuPool;
+use hyper::Method;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio_core::reactor::{self, Core, Interval};
+
+pub fn serve(config: Config, path: PathBuf, history: History, mut lock: Lock) -> Result<()> {
+    // Share the configuration
+    let shared = Shared::new(config);
+    let config = shared.get();
+
+    // Bind the sockets or take them over from systemd
+    let passed = systemd::listeners()?;
+    let (sockets, metrics_socket) = if passed.is_empty() {
+        let (_, sockets, metrics_socket) = bind(&config)?;
+        (sockets, metrics_socket)
+    } else {
+        take_over(&config, passed)?
+    };
+
+    // Save the sockets into the lock file, e.g. for `dynonym status`
+    lock.set_sockets(sockets.iter().chain(metrics_socket.iter()).map(Socket::to_string).collect())?;
+
+    // Drop privileges, e.g. after binding a privileged port as root
+    // Note: Within the new root directory, every path has to be translated.
+    let chroot = config.http.chroot.as_ref().map(PathBuf::as_path);
+    let lock_path = privileges::rebase(lock.path(), chroot)?;
+    let config_path = privileges::rebase(&path, chroot)?;
+    let audit_path = match config.audit.file {
+        Some(ref file) => Some(privileges::rebase(file, chroot)?),
+        None => None,
+    };
+    // Note: The configuration file must stay readable for reloading it. It is only handed over if
+    // an outdated password hash is to be rehashed. Rehashing replaces the file, hence its
+    // directory must be writable, too.
+    let rehashing = config.users.values().any(|user| user.pw.needs_rehash(&config.hashing));
+    {
+        let mut files = vec![lock.path(), history.path()];
+        let mut dirs = vec![parent(lock.path())];
+        if rehashing {
+            files.push(&path);
+            dirs.push(parent(&path));
+        }
+        if let Some(ref audit_file) = config.audit.file {
+            files.push(audit_file);
+            dirs.push(parent(audit_file));
+        }
+        privileges::drop(&config.http, &[&path], &files, &dirs)?;
+    }
+    lock.relocate(lock_path);
+
+    // Reload the configuration on SIGHUP or when the file changes
+    reload::watch(config_path.clone(), shared.clone())?;
+
+    // Shut down gracefully when receiving a signal
+    let shutdown = Shutdown::new();
+    shutdown::watch(shutdown.clone(), config.http.grace, lock)?;
+
+    // Start the HTTP server at every socket
+    let mut core = Core::new().chain_err(|| ErrorKind::HttpReactor)?;
+    let handle = core.handle();
+    let server = Server {
+        config_file: if rehashing { Some(config_path) } else { None },
+        audit_file: audit_path,
+        history: Some(history),
+        shutdown,
+        ..Server::shared(shared)
+    };
+    let context = server.run(sockets, metrics_socket, &handle)?;
+
+    // Ping the systemd watchdog as long as the DNS server is reachable
+    systemd::watchdog(context.shared.clone(), context.backend.clone(), &handle)?;
+    systemd::notify("READY=1");
+    ::daemon::ready();
+
+    // Note: The event loop runs until the process exits, see `shutdown::watch`.
+    core.run(future::empty())
+}
+
+/// A builder for an HTTP server that runs in a background thread, e.g. within another application
+/// or a test.
+///
+/// Unlike [`serve`], a `Server` leaves the process alone: It neither takes over sockets from
+/// systemd nor drops privileges, handles signals or watches the configuration file. Without a
+/// [`History`], updates are not recorded, and without a configuration file, outdated password
+/// hashes are not rehashed.
+///
+/// [`serve`]: fn.serve.html
+/// [`History`]: ../history/struct.History.html
+pub struct Server {
+    shared: Shared,
+    backend: Option<Arc<Backend>>,
+    routes: Vec<Route>,
+    hooks: Hooks,
+    history: Option<History>,
+    config_file: Option<PathBuf>,
+    audit_file: Option<PathBuf>,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Creates a new server with the given configuration.
+    pub fn new(config: Config) -> Self {
+        Self::shared(Shared::new(config))
+    }
+
+    /// Creates a new server with the given shared configuration, e.g. in order to change the
+    /// configuration while the server is running.
+    pub fn shared(shared: Shared) -> Self {
+        let audit_file = shared.get().audit.file.clone();
+        Server {
+            shared,
+            backend: None,
+            routes: Vec::new(),
+            hooks: Hooks::new(),
+            history: None,
+            config_file: None,
+            audit_file,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Performs the DNS operations using the given backend instead of sending DNS UPDATE messages.
+    pub fn backend<B: Backend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Adds the given route. It takes precedence over the built-in routes.
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    /// Adds the given hook, which is notified about every event recorded in the audit log.
+    pub fn hook<F>(mut self, hook: F) -> Self
+        where F: Fn(&Event) + Send + Sync + 'static
+    {
+        self.hooks.add(hook);
+        self
+    }
+
+    /// Records the updates in the given history.
+    pub fn history(mut self, history: History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Rehashes outdated password hashes within the configuration file at the given path.
+    pub fn config_file(mut self, path: PathBuf) -> Self {
+        self.config_file = Some(path);
+        self
+    }
+
+    /// Validates the configuration, binds the sockets and starts the server in a background
+    /// thread.
+    pub fn start(self) -> Result<Handle> {
+        let config = self.shared.get();
+        config.validate()?;
+        let (addr, sockets, metrics_socket) = bind(&config)?;
+
+        // Run the event loop until the handle stops it
+        let shutdown = self.shutdown.clone();
+        let (started, startup) = mpsc::channel();
+        let (stop, stopped) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let result = Core::new().chain_err(|| ErrorKind::HttpReactor).and_then(|core| {
+                self.run(sockets, metrics_socket, &core.handle())?;
+                Ok(core)
+            });
+            match result {
+                Ok(mut core) => {
+                    let _ = started.send(Ok(()));
+                    let _ = core.run(stopped);  // Note: Dropping the sender stops, too.
+                },
+                Err(err) => {
+                    let _ = started.send(Err(err));
+                },
+            }
+        });
+
+        // Wait until the server runs
+        match startup.recv() {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => return Err(err),
+            Err(_) => bail!(ErrorKind::HttpThread),
+        }
+
+        Ok(Handle {
+            addr,
+            grace: Duration::from_secs(config.http.grace),
+            shutdown,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Serves the routes at the given sockets on the event loop of the given handle and returns
+    /// the context shared by the routes.
+    fn run(self, sockets: Vec<Socket>, metrics_socket: Option<Socket>, handle: &reactor::Handle)
+        -> Result<Arc<Context>>
+    {
+        // Set up the state shared by the routes, including the threads verifying passwords
+        // Note: A thread pool cannot be empty.
+        let config = self.shared.get();
+        let metrics = Arc::new(Registry::new());
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => Arc::new(Rfc2136::new().with_metrics(metrics.clone())),
+        };
+        let context = Arc::new(Context {
+            shared: self.shared.clone(),
+            cache: cache::Cache::new(config.http.cache),
+            lockout: lockout::Tracker::new(config.http.lockout.clone()),
+            rehasher: rehash::Rehasher::new(self.config_file, config.hashing),
+            limiter: limit::Limiter::new(config.http.limits.clone()),
+            probe: probe::Probe::new(probe::TTL),
+            history: self.history,
+            audit: Trail::new(self.audit_file),
+            hooks: self.hooks,
+            metrics,
+            shutdown: self.shutdown,
+            backend,
+            pool: CpuPool::new(usize::from(config.http.workers.max(1))),
+        });
+
+        // Forget about old failed logins periodically
+        let ctx = context.clone();
+        let prunes = Interval::new(context.lockout.period(), handle)
+            .chain_err(|| ErrorKind::HttpReactor)?
+            .map_err(|err| error!("The lockout timer failed: {}", err))
+            .for_each(move |_| {
+                ctx.lockout.prune();
+                Ok(())
+            });
+        handle.spawn(prunes);
+
+        // Serve the routes added to the builder first, then the built-in ones
+        let mut handlers = self.routes;
+        handlers.extend(vec![
+            Route::new(Method::Get, "/dns/update", routes::dns::update),
+            Route::new(Method::Post, "/api/v1/update", routes::dns::update_json),
+            Route::new(Method::Get, "/dns/status", routes::dns::status),
+            Route::new(Method::Get, "/health", routes::health::health),
+            Route::new(Method::Get, "/ready", routes::health::ready),
+            Route::new(Method::Get, "/ip", routes::ip),
+            Route::new(Method::Get, "/port", routes::port),
+            Route::new(Method::Get, "/socket", routes::socket),
+        ]);
+
+        // Serve the metrics, if requested, at their separate socket
+        if let Some(metrics_socket) = metrics_socket {
+            let metrics = Route::new(Method::Get, "/metrics", routes::metrics);
+            let router = Router::new(vec![metrics], context.clone());
+            metrics_socket.serve(Rc::new(router), handle)?;
+        }
+
+        // Start the HTTP server at every socket
+        let router = Rc::new(Router::new(handlers, context.clone()));
+        for socket in sockets {
+            socket.serve(router.clone(), handle)?;
+        }
+        Ok(context)
+    }
+}
+
+/// A handle to a server running in a background thread.
+///
+/// Dropping the handle stops the server right away, i.e. without waiting for the updates in
+/// progress.
+#[derive(Debug)]
+pub struct Handle {
+    addr: SocketAddr,
+    grace: Duration,
+    shutdown: Shutdown,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Handle {
+    /// Returns the address of the main TCP socket, e.g. the port chosen by the operating system if
+    /// the configured port is `0`.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Shuts the server down gracefully: New updates are refused and the updates in progress may
+    /// finish within the configured grace period before the server stops.
+    pub fn shutdown(mut self) -> Result<()> {
+        self.shutdown.stop();
+        if !self.shutdown.wait(self.grace) {
+            warn!("Stopping while {} update(s) are still in progress", self.shutdown.active());
+        }
+        self.stop()
+    }
+
+    /// Stops the event loop and waits for its thread to finish.
+    fn stop(&mut self) -> Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());  // Note: The event loop may have stopped already.
+        }
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| ErrorKind::HttpThread.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            error!("Cannot stop the HTTP server: {}", err.describe());
+        }
+    }
+}
+
+/// Binds the HTTP sockets and, if requested, the separate metrics socket. Returns the address of
+/// the main TCP socket, e.g. the port chosen by the operating system, too.
+fn bind(config: &Config) -> Result<(SocketAddr, Vec<Socket>, Option<Socket>)> {
+    let metrics_socket = bind_metrics(config, &mut Vec::new())?;
+
+    // Bind the main socket, then the other sockets
+    let socket = config.http.socket;
+    let tcp = TcpListener::bind(socket).chain_err(|| ErrorKind::HttpBind(socket))?;
+    let addr = tcp.local_addr().chain_err(|| ErrorKind::HttpBind(socket))?;
+    let mut sockets = vec![Socket::Tcp(tcp)];
+    for listener in &config.http.listeners {
+        sockets.push(Socket::bind(listener)?);
+    }
+
+    Ok((addr, sockets, metrics_socket))
+}
+
+/// Takes over the given sockets passed by systemd instead of binding the HTTP sockets: The one
+/// matching the metrics socket address serves the metrics, the others serve everything else. Every
+/// configured listener has to be among them.
+fn take_over(config: &Config, mut passed: Vec<Socket>) -> Result<(Vec<Socket>, Option<Socket>)> {
+    let metrics_socket = bind_metrics(config, &mut passed)?;
+
+    // Take over the other sockets, applying the settings of the configured listeners
+    // Note: The sockets passed by systemd replace the main socket address.
+    let mut sockets = Vec::new();
+    for listener in &config.http.listeners {
+        match passed.iter().position(|passed| passed.matches(listener)) {
+            Some(i) => sockets.push(passed.remove(i).configure(listener)),
+            None => bail!(ErrorKind::SystemdListener(listener.to_string())),
+        }
+    }
+    sockets.extend(passed);
+
+    Ok((sockets, metrics_socket))
+}
+
+/// Binds the separate metrics socket, if requested, unless it is among the given sockets passed by
+/// systemd.
+fn bind_metrics(config: &Config, passed: &mut Vec<Socket>) -> Result<Option<Socket>> {
+    let socket = match config.http.metrics {
+        Metrics { enabled: true, socket } => socket,
+        Metrics { enabled: false, .. } => return Ok(None),
+    };
+    let listener = Listener::Tcp { socket };
+    Ok(Some(match passed.iter().position(|passed| passed.matches(&listener)) {
+        Some(i) => passed.remove(i),
+        None => Socket::bind(&listener)?,
+    }))
+}
+
+/// Returns the directory containing the file at the given path.
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns::{Client, Outcome};
+    use errors::Error;
+    use http::service::answer;
+    use types::{Domain, Rcode};
+
+    use futures::Future;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, TcpStream};
+    use std::sync::Mutex;
+
+    /// A backend keeping the records in memory.
+    #[derive(Clone, Debug, Default)]
+    struct Memory(Arc<Mutex<Vec<(Domain, IpAddr)>>>);
+
+    impl Backend for Memory {
+        fn connect(&self, _: SocketAddr, _: u32, _: &reactor::Handle) -> Box<Client> {
+            Box::new(self.clone())
+        }
+    }
+
+    impl Client for Memory {
+        fn update(&self, domain: Domain, ip: IpAddr) -> Box<Future<Item = Outcome, Error = Error>> {
+            self.0.lock().unwrap().push((domain, ip));
+            Box::new(future::ok(Outcome::Changed))
+        }
+
+        fn query(&self, domain: Domain) -> Box<Future<Item = Vec<IpAddr>, Error = Error>> {
+            let records = self.0.lock().unwrap();
+            let ips = records.iter().filter(|&&(ref d, _)| *d == domain).map(|&(_, ip)| ip);
+            Box::new(future::ok(ips.collect()))
+        }
+
+        fn probe(&self) -> Box<Future<Item = Rcode, Error = Error>> {
+            Box::new(future::ok(Rcode::NOERROR))
+        }
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        get_as(addr, path, "tobias:s3cr3t")
+    }
+
+    fn get_as(addr: SocketAddr, path: &str, creds: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\n\
+             Connection: close\r\n\r\n",
+            path,
+            ::base64::encode(creds),
+        ).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn server_embedded() {
+        let mut config = Config::default();
+        config.http.socket = "127.0.0.1:0".parse().unwrap();
+        config.users.add("tobias", "s3cr3t");
+        config.user_mut("tobias").unwrap().domains.add("a.example.org".parse().unwrap());
+
+        let backend = Memory::default();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server = {
+            let events = events.clone();
+            Server::new(config)
+                .backend(backend.clone())
+                .route(Route::new(Method::Get, "/version", |_, _| answer("1.0")))
+                .hook(move |event| events.lock().unwrap().push(event.clone()))
+                .start()
+                .unwrap()
+        };
+        let addr = server.addr();
+        assert_ne!(addr.port(), 0);
+
+        assert!(get(addr, "/version").ends_with("\r\n\r\n1.0"));
+        assert!(get(addr, "/ready").starts_with("HTTP/1.1 200 OK"));
+        assert!(get(addr, "/dns/update?domain=a.example.org&ipv4=192.0.2.1")
+            .starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(
+            *backend.0.lock().unwrap(),
+            vec![("a.example.org".parse().unwrap(), "192.0.2.1".parse().unwrap())],
+        );
+        match events.lock().unwrap().last() {
+            Some(&Event::Update { ref domain, .. }) => {
+                assert_eq!(domain.to_string(), "a.example.org");
+            },
+            event => panic!("Unexpected event: {:?}", event),
+        }
+
+        server.shutdown().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn server_invalid_config() {
+        let mut config = Config::default();
+        config.http.socket = "127.0.0.1:0".parse().unwrap();
+        config.http.chroot = Some("/var/lib/dynonym".into());
+        assert!(Server::new(config).backend(Memory::default()).start().is_err());
+    }
+
+    #[test]
+    fn server_client_checked_first() {
+        let mut config = Config::default();
+        config.http.socket = "127.0.0.1:0".parse().unwrap();
+        config.users.add("tobias", "s3cr3t");
+        config.user_mut("tobias").unwrap().clients.add("192.0.2.0/24".parse().unwrap());
+        let server = Server::new(config).backend(Memory::default()).start().unwrap();
+
+        // Note: The response must not tell whether the password is correct.
+        let path = "/dns/update?domain=a.example.org&ipv4=192.0.2.1";
+        let right = get_as(server.addr(), path, "tobias:s3cr3t");
+        let wrong = get_as(server.addr(), path, "tobias:wrong");
+        assert!(right.starts_with("HTTP/1.1 403 Forbidden"));
+        assert_eq!(right.split("\r\n\r\n").last(), wrong.split("\r\n\r\n").last());
+        assert!(wrong.starts_with("HTTP/1.1 403 Forbidden"));
+    }
+
+    #[test]
+    fn server_stopping() {
+        let mut config = Config::default();
+        config.http.socket = "127.0.0.1:0".parse().unwrap();
+        let shutdown = Shutdown::new();
+        let server = Server {
+            shutdown: shutdown.clone(),
+            ..Server::new(config).backend(Memory::default())
+        };
+        let server = server.start().unwrap();
+
+        // Note: A connection accepted before the shutdown is still served.
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\nOK") {
+            let mut buf = [0; 256];
+            let read = stream.read(&mut buf).unwrap();
+            assert_ne!(read, 0);
+            response.extend_from_slice(&buf[..read]);
+        }
+        shutdown.stop();
+
+        // Note: The sockets are closed as soon as the server notices the shutdown.
+        let closed = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            TcpStream::connect(server.addr()).is_err()
+        });
+        assert!(closed);
+
+        stream.write_all(b"GET /ready HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+    }
 }
@@ -0,0 +1,238 @@
+//! Privilege dropping
+//!
+//! Binding a privileged port, e.g. port 80, requires root privileges. Handling requests does not.
+//! Thus, the server may drop its privileges right after binding its sockets: It changes its root
+//! directory (if configured) and switches to an unprivileged user and group.
+//!
+//! Since the server keeps writing the lock file and its state files, e.g. the history file, they
+//! are handed over to the new user beforehand. If the server cannot write them afterwards, it
+//! refuses to go on. The same holds for files it merely reads, e.g. the configuration file.
+//!
+//! Within the new root directory, every path has to be translated, see [`rebase`].
+//!
+//! [`rebase`]: fn.rebase.html
+
+use config::Http;
+use errors::*;
+
+use libc::{self, gid_t, uid_t};
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// The size of the buffer used to look up users and groups.
+const BUF_SIZE: usize = 16384;
+
+/// Drops the privileges as configured, i.e. changes the root directory and switches the user and
+/// the group. Afterwards, the given readable files must be readable, the given files must be
+/// writable and, within the given directories, files must be creatable and removable.
+///
+/// The paths must be given as seen from outside of the new root directory.
+pub fn drop(http: &Http, readable: &[&Path], files: &[&Path], dirs: &[&Path]) -> Result<()> {
+    // Look up the user and the group
+    // Note: Without a configured group, the server runs as the primary group of the user. This
+    // must neither be unknown nor root.
+    let user = match http.user {
+        Some(ref name) => Some((name, lookup_user(name)?)),
+        None => None,
+    };
+    let gid = match (http.group.as_ref(), user) {
+        (Some(name), _) => Some(lookup_group(name)?),
+        (None, Some((_, (_, Some(gid))))) if gid != 0 => Some(gid),
+        (None, Some((name, _))) => bail!(ErrorKind::PrivilegesMissingGroup(name.clone())),
+        (None, None) => None,
+    };
+    let user = user.map(|(_, (uid, _))| uid);
+
+    // Translate the paths before changing the root directory
+    let chroot = http.chroot.as_ref().map(PathBuf::as_path);
+    let rebased_readable =
+        readable.iter().map(|path| rebase(path, chroot)).collect::<Result<Vec<_>>>()?;
+    let rebased_files = files.iter().map(|path| rebase(path, chroot)).collect::<Result<Vec<_>>>()?;
+    let rebased_dirs = dirs.iter().map(|path| rebase(path, chroot)).collect::<Result<Vec<_>>>()?;
+
+    // Hand the files over to the new user and group
+    if user.is_some() || gid.is_some() {
+        let uid = user.unwrap_or(!0);
+        let gid = gid.unwrap_or(!0);
+        for path in files.iter().filter(|path| path.exists()) {
+            chown(path, uid, gid).chain_err(|| ErrorKind::PrivilegesChown(path.to_path_buf()))?;
+        }
+    }
+
+    // Change the root directory
+    if let Some(dir) = chroot {
+        c_path(dir)
+            .and_then(|c_dir| check(unsafe { libc::chroot(c_dir.as_ptr()) }))
+            .and_then(|_| env::set_current_dir("/"))
+            .chain_err(|| ErrorKind::PrivilegesChroot(dir.to_path_buf()))?;
+        info!("Changed the root directory to '{}'", dir.display());
+    }
+
+    // Switch the group
+    // Note: The group must be switched first since the new user may not be allowed to do so.
+    if let Some(gid) = gid {
+        // Note: Only root may (and has to) drop the supplementary groups.
+        let is_root = unsafe { libc::geteuid() } == 0;
+        let groups = if is_root { check(unsafe { libc::setgroups(1, &gid) }) } else { Ok(()) };
+        groups
+            .and_then(|_| check(unsafe { libc::setgid(gid) }))
+            .chain_err(|| ErrorKind::PrivilegesGroup(gid))?;
+        info!("Switched to group {}", gid);
+    }
+
+    // Switch the user and make sure there is no way back
+    if let Some(uid) = user {
+        check(unsafe { libc::setuid(uid) }).chain_err(|| ErrorKind::PrivilegesUser(uid))?;
+        if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            bail!(ErrorKind::PrivilegesUser(uid));
+        }
+        info!("Switched to user {}", uid);
+    }
+
+    // Make sure the files are still readable and writable, respectively
+    if let Some(path) = rebased_readable.iter().find(|path| !is_readable(path)) {
+        bail!(ErrorKind::PrivilegesUnreadable(path.clone()));
+    }
+    let unwritable = rebased_files
+        .iter()
+        .filter(|path| path.exists())
+        .chain(rebased_dirs.iter())
+        .find(|path| !is_writable(path));
+    if let Some(path) = unwritable {
+        bail!(ErrorKind::PrivilegesUnwritable(path.clone()));
+    }
+
+    Ok(())
+}
+
+/// Translates the given path into the path seen from within the given root directory, if any.
+///
+/// Relative paths are resolved against the current working directory. Fails if the path is
+/// outside of the root directory.
+pub fn rebase(path: &Path, root: Option<&Path>) -> Result<PathBuf> {
+    let root = match root {
+        Some(root) => root,
+        None => return Ok(path.into()),
+    };
+    let outside = || ErrorKind::PrivilegesOutside(path.into(), root.into());
+
+    // Resolve the path, though the file itself may not exist yet
+    // Note: `..` would be ambiguous before resolving symbolic links.
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if dir != Path::new("") => (dir, name),
+        (_, Some(name)) => (Path::new("."), name),
+        _ => bail!(outside()),
+    };
+    let dir = fs::canonicalize(dir).chain_err(&outside)?;
+    let root = fs::canonicalize(root).chain_err(&outside)?;
+
+    // Strip the root directory
+    let inner = dir.strip_prefix(&root).map_err(|_| outside())?;
+    Ok(Path::new("/").join(inner).join(name))
+}
+
+/// Looks up the user with the given name (or ID). Returns the user ID and the ID of the primary
+/// group, if the user has an entry in the user database.
+pub fn lookup_user(name: &str) -> Result<(uid_t, Option<gid_t>)> {
+    let c_name = CString::new(name).chain_err(|| ErrorKind::PrivilegesUnknownUser(name.into()))?;
+    let mut buf = vec![0; BUF_SIZE];
+    let mut entry: libc::passwd = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result);
+    }
+    if !result.is_null() {
+        return Ok((entry.pw_uid, Some(entry.pw_gid)));
+    }
+
+    // Fall back to a numeric ID
+    // Note: A user without an entry has no primary group.
+    match name.parse() {
+        Ok(uid) => Ok((uid, None)),
+        Err(_) => bail!(ErrorKind::PrivilegesUnknownUser(name.into())),
+    }
+}
+
+/// Looks up the group with the given name (or ID).
+pub fn lookup_group(name: &str) -> Result<gid_t> {
+    let c_name = CString::new(name).chain_err(|| ErrorKind::PrivilegesUnknownGroup(name.into()))?;
+    let mut buf = vec![0; BUF_SIZE];
+    let mut entry: libc::group = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), &mut entry, buf.as_mut_ptr(), buf.len(), &mut result);
+    }
+    if !result.is_null() {
+        return Ok(entry.gr_gid);
+    }
+
+    // Fall back to a numeric ID
+    name.parse().map_err(|_| ErrorKind::PrivilegesUnknownGroup(name.into()).into())
+}
+
+/// Changes the owner and the group of the file at the given path. `!0` keeps the owner or the
+/// group, respectively.
+pub fn chown(path: &Path, uid: uid_t, gid: gid_t) -> io::Result<()> {
+    c_path(path).and_then(|c_path| check(unsafe { libc::chown(c_path.as_ptr(), uid, gid) }))
+}
+
+/// Turns the given return value of a libc function into a result.
+fn check(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Checks whether the current user may read the file at the given path.
+fn is_readable(path: &Path) -> bool {
+    c_path(path)
+        .map(|c_path| unsafe { libc::access(c_path.as_ptr(), libc::R_OK) } == 0)
+        .unwrap_or(false)
+}
+
+/// Checks whether the current user may write the file (or directory) at the given path.
+fn is_writable(path: &Path) -> bool {
+    c_path(path)
+        .map(|c_path| unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0)
+        .unwrap_or(false)
+}
+
+/// Converts the given path into a C string.
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn rebase_path() {
+        let root = NamedTempFile::new().unwrap().path().to_owned();
+        let dir = root.join("state");
+        fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(rebase(&dir.join("dynonym.lock"), None).unwrap(), dir.join("dynonym.lock"));
+        assert_eq!(
+            rebase(&dir.join("dynonym.lock"), Some(&root)).unwrap(),
+            Path::new("/state/dynonym.lock"),
+        );
+        assert_eq!(
+            rebase(&dir.join("../dynonym.toml"), Some(&root)).unwrap(),
+            Path::new("/dynonym.toml"),
+        );
+        assert!(rebase(Path::new("/dynonym.toml"), Some(&dir)).is_err());
+        assert!(rebase(&dir.join("missing/dynonym.toml"), Some(&root)).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Probes of the DNS servers
+//!
+//! The server is only ready to perform updates if it can reach its DNS servers. A [`Probe`] checks
+//! every DNS server by sending an SOA query for the root zone: Any response (even `REFUSED`) shows
+//! that the DNS server is reachable. In order to keep load balancers and watchdogs from flooding
+//! the DNS servers, the results are cached for a short time.
+//!
+//! [`Probe`]: struct.Probe.html
+
+use dns::Backend;
+use types::Rcode;
+
+use futures::{future, Future, IntoFuture};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Handle;
+
+/// The time (in seconds) the results of a probe are cached.
+pub const TTL: u64 = 5;
+
+/// The result of probing a DNS server.
+#[derive(Clone, Debug, Serialize)]
+pub struct Target {
+    /// The socket address of the DNS server.
+    pub socket: SocketAddr,
+
+    /// Whether the DNS server responded.
+    pub ready: bool,
+
+    /// The response code, if the DNS server responded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rcode: Option<Rcode>,
+
+    /// The reason why the DNS server is not ready, if it is not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// The time (in milliseconds) until the DNS server responded or the probe failed.
+    pub duration: u64,
+}
+
+/// A cached probe of the DNS servers.
+#[derive(Debug)]
+pub struct Probe {
+    ttl: Duration,
+    last: Arc<Mutex<Option<(Instant, Vec<Target>)>>>,
+}
+
+impl Probe {
+    /// Creates a new probe that caches its results for the given time (in seconds).
+    pub fn new(ttl: u64) -> Self {
+        Probe {
+            ttl: Duration::from_secs(ttl),
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Probes the DNS servers at the given socket addresses using the given backend, unless there
+    /// are recent results.
+    pub fn check(&self, backend: &Backend, handle: &Handle, targets: &[SocketAddr])
+        -> Box<Future<Item = Vec<Target>, Error = ()>>
+    {
+        self.check_with(targets, |socket| probe(backend, socket, handle))
+    }
+
+    fn check_with<F, T>(&self, targets: &[SocketAddr], probe: F)
+        -> Box<Future<Item = Vec<Target>, Error = ()>>
+        where F: Fn(SocketAddr) -> T,
+              T: IntoFuture<Item = Target, Error = ()> + 'static,
+    {
+        if let Some((ref time, ref results)) = *self.last.lock().unwrap() {
+            let same = results.iter().map(|target| target.socket).eq(targets.iter().cloned());
+            if same && time.elapsed() < self.ttl {
+                return Box::new(future::ok(results.clone()));
+            }
+        }
+
+        // Note: The DNS servers are probed concurrently.
+        let probes: Vec<_> = targets.iter().map(|&socket| probe(socket)).collect();
+        let last = self.last.clone();
+        Box::new(future::join_all(probes).map(move |results| {
+            *last.lock().unwrap() = Some((Instant::now(), results.clone()));
+            results
+        }))
+    }
+}
+
+/// Probes the DNS server at the given socket address using the given backend.
+fn probe(backend: &Backend, socket: SocketAddr, handle: &Handle)
+    -> Box<Future<Item = Target, Error = ()>>
+{
+    let start = Instant::now();
+    let probe = backend.connect(socket, 0, handle).probe().then(move |result| {
+        let elapsed = start.elapsed();
+        let duration = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos()) / 1_000_000;
+
+        Ok(match result {
+            Ok(rcode) => {
+                Target { socket, ready: true, rcode: Some(rcode), reason: None, duration }
+            },
+            Err(err) => {
+                let reason = err.describe();
+                warn!("DNS server '{}' is not ready: {}", socket, reason);
+                Target { socket, ready: false, rcode: None, reason: Some(reason), duration }
+            },
+        })
+    });
+    Box::new(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn target(socket: SocketAddr) -> Target {
+        Target { socket, ready: true, rcode: Some(Rcode::NOERROR), reason: None, duration: 1 }
+    }
+
+    #[test]
+    fn probe_cached() {
+        let probe = Probe::new(60);
+        let socket = "127.0.0.1:53".parse().unwrap();
+        let count = Cell::new(0);
+        let counting = |socket| { count.set(count.get() + 1); Ok(target(socket)) };
+
+        assert!(probe.check_with(&[socket], &counting).wait().unwrap()[0].ready);
+        assert!(probe.check_with(&[socket], &counting).wait().unwrap()[0].ready);
+        assert_eq!(count.get(), 1);
+
+        // Other targets are probed again
+        let other = "127.0.0.1:5353".parse().unwrap();
+        assert_eq!(probe.check_with(&[other], &counting).wait().unwrap()[0].socket, other);
+        assert_eq!(count.get(), 2);
+    }
+
+    #[test]
+    fn probe_uncached() {
+        let probe = Probe::new(0);
+        let socket = "127.0.0.1:53".parse().unwrap();
+        let count = Cell::new(0);
+        let counting = |socket| { count.set(count.get() + 1); Ok(target(socket)) };
+
+        probe.check_with(&[socket], &counting).wait().unwrap();
+        probe.check_with(&[socket], &counting).wait().unwrap();
+        assert_eq!(count.get(), 2);
+    }
+}
@@ -0,0 +1,83 @@
+//! Transparent rehashing of passwords
+//!
+//! When the configured password hashing [`Algorithm`] (or its parameters) changes, existing
+//! password hashes become outdated. Since the plain password is only known during a login, a
+//! [`Rehasher`] hashes it again right after a successful login and saves the new hash into the
+//! configuration file.
+//!
+//! [`Algorithm`]: ../../types/enum.Algorithm.html
+//! [`Rehasher`]: struct.Rehasher.html
+
+use config::Config;
+use errors::*;
+use types::{Algorithm, Hash};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A rehasher for outdated password hashes.
+///
+/// A `Rehasher` without a path, e.g. for a configuration that has not been loaded from a file, is
+/// disabled.
+#[derive(Debug)]
+pub struct Rehasher {
+    path: Option<PathBuf>,
+    algorithm: Algorithm,
+    done: Mutex<HashSet<String>>,
+    file: Mutex<()>,
+}
+
+impl Rehasher {
+    /// Creates a new rehasher given the path of the configuration file, if any, and the desired
+    /// algorithm.
+    pub fn new(path: Option<PathBuf>, algorithm: Algorithm) -> Self {
+        Rehasher {
+            path,
+            algorithm,
+            done: Mutex::new(HashSet::new()),
+            file: Mutex::new(()),
+        }
+    }
+
+    /// Rehashes the given user's password, if the given hash is outdated.
+    ///
+    /// This method must only be called after the password has been verified. Errors are logged
+    /// but not returned since they must not fail the login.
+    pub fn rehash(&self, user: &str, pw: &str, hash: &Hash) {
+        let path = match self.path {
+            Some(ref path) if hash.needs_rehash(&self.algorithm) => path,
+            _ => return,
+        };
+
+        // Rehash at most once per user
+        // Note: The configuration held in memory still contains the outdated hash.
+        if !self.done.lock().unwrap().insert(user.into()) {
+            return;
+        }
+
+        // Note: Hashing takes a while on purpose, thus no lock is held meanwhile. Only updating
+        // the configuration file is serialized.
+        let stored = Hash::new(pw, &self.algorithm).and_then(|new| {
+            let _file = self.file.lock().unwrap();
+            store(path, user, hash, new)
+        });
+        match stored {
+            Ok(true) => info!("Rehashed the password of user '{}'", user),
+            Ok(false) => warn!("Cannot rehash the password of user '{}': changed on disk", user),
+            Err(err) => error!("Cannot rehash the password of user '{}': {}", user, err),
+        }
+    }
+}
+
+/// Replaces the old hash by the new one within the configuration file at the given path, unless
+/// the user's password has been changed on disk in the meantime.
+fn store(path: &Path, user: &str, old: &Hash, new: Hash) -> Result<bool> {
+    let mut config = Config::load(path)?;
+    match config.user_mut(user) {
+        Some(setting) if setting.pw == *old => setting.pw = new,
+        _ => return Ok(false),
+    }
+    config.store(path)?;
+    Ok(true)
+}
@@ -0,0 +1,120 @@
+//! Reloading of the configuration
+//!
+//! The routes read the configuration through a [`Shared`] handle. A watcher thread replaces the
+//! configuration as soon as the process receives a SIGHUP or the configuration file changes. The
+//! new configuration is validated first: An invalid configuration is logged and discarded, i.e.
+//! the server keeps using the current one. Requests in progress finish with the configuration they
+//! started with.
+//!
+//! Changes to the users and to the DNS configuration take effect immediately. Changes to any other
+//! section, e.g. `[http]`, require a restart: The state built from them, e.g. the rate limits or
+//! the audit log, lives as long as the server. Thus, a configuration changing them is discarded as
+//! a whole, rather than applied in part.
+//!
+//! [`Shared`]: ../../config/struct.Shared.html
+
+use config::{Config, Shared};
+use errors::*;
+
+use libc::{self, c_int};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use toml;
+
+/// The interval (in seconds) at which the watcher checks for a SIGHUP or a changed configuration
+/// file.
+const INTERVAL: u64 = 1;
+
+/// Whether a SIGHUP was received since the last check.
+static SIGHUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sighup(_: c_int) {
+    SIGHUP.store(true, Ordering::SeqCst);
+}
+
+/// Starts a thread that reloads the configuration file at the given path into the given handle.
+pub fn watch(path: PathBuf, shared: Shared) -> Result<()> {
+    // Set up the SIGHUP handler
+    // Note: The handler merely sets a flag since almost nothing is safe within a signal handler.
+    let handler = handle_sighup as extern "C" fn(c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGHUP, handler) } == libc::SIG_ERR {
+        bail!(ErrorKind::ConfigSetupSighupHandler);
+    }
+
+    // Watch for signals and changes
+    let mut modified = mtime(&path);
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(INTERVAL));
+        let signaled = SIGHUP.swap(false, Ordering::SeqCst);
+        let current = mtime(&path);
+        if signaled || current != modified {
+            modified = current;
+            reload(&path, &shared);
+        }
+    });
+
+    Ok(())
+}
+
+/// Loads, validates and swaps in the configuration file at the given path.
+fn reload(path: &Path, shared: &Shared) {
+    let config = Config::load(path)
+        .and_then(|config| config.validate().map(|_| config))
+        .and_then(|config| restart(&config, &shared.get()).map(|_| config));
+    match config {
+        Ok(config) => {
+            shared.set(config);
+            info!("Reloaded the configuration from '{}'", path.display());
+        },
+        Err(err) => error!(
+            "Cannot reload the configuration, keeping the current one: {}",
+            err.describe()
+        ),
+    }
+}
+
+/// Fails if the given new configuration changes any section that requires a restart, naming these
+/// sections.
+fn restart(new: &Config, old: &Config) -> Result<()> {
+    let mut sections = Vec::new();
+    if toml::to_string(&new.http).ok() != toml::to_string(&old.http).ok() {
+        sections.push("[http]");
+    }
+    if toml::to_string(&new.audit).ok() != toml::to_string(&old.audit).ok() {
+        sections.push("[audit]");
+    }
+    if new.hashing != old.hashing {
+        sections.push("[hashing]");
+    }
+    if !sections.is_empty() {
+        bail!(ErrorKind::ConfigRestart(sections.join(", ")));
+    }
+    Ok(())
+}
+
+/// Returns the time the file at the given path was modified last, if available.
+fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::Algorithm;
+
+    #[test]
+    fn reload_restart() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.dns.ttl = 300;
+        assert!(restart(&new, &old).is_ok());
+
+        new.http.grace = 1;
+        new.hashing = Algorithm::Bcrypt { cost: 10 };
+        let err = restart(&new, &old).unwrap_err();
+        assert_eq!(err.to_string(), "Changes to [http], [hashing] require a restart");
+    }
+}
@@ -0,0 +1,167 @@
+//! Requests
+//!
+//! A [`Request`] is an HTTP request whose body has been received completely. Besides the usual
+//! parts of a request, it provides the client's address and a handle to the event loop, e.g. for
+//! sending DNS messages.
+//!
+//! Query strings are parsed into [`Fields`].
+//!
+//! [`Request`]: struct.Request.html
+//! [`Fields`]: struct.Fields.html
+
+use http::errors::Failure;
+
+use hyper::{Headers, Method, StatusCode};
+use hyper::header::{Accept, ContentType};
+use hyper::mime;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tokio_core::reactor::Handle;
+use url::form_urlencoded;
+
+/// The maximum size (in bytes) of a request body.
+pub const BODY_LIMIT: usize = 1 << 20;
+
+/// A request whose body has been received completely.
+#[derive(Debug)]
+pub struct Request {
+    method: Method,
+    path: String,
+    query: Option<String>,
+    headers: Headers,
+    remote: SocketAddr,
+    body: Vec<u8>,
+    handle: Handle,
+}
+
+impl Request {
+    /// Assembles a request from its parts.
+    pub fn new(
+        method: Method,
+        path: String,
+        query: Option<String>,
+        headers: Headers,
+        remote: SocketAddr,
+        body: Vec<u8>,
+        handle: Handle,
+    ) -> Self {
+        Request { method, path, query, headers, remote, body, handle }
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the path of the request, excluding the query string.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the query string of the request, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_ref().map(String::as_str)
+    }
+
+    /// Returns the headers of the request.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns the client's address.
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Returns the body of the request.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns a handle to the event loop the request is handled on.
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// Checks whether the body of the request is JSON according to its `Content-Type` header.
+    pub fn is_json(&self) -> bool {
+        self.headers
+            .get::<ContentType>()
+            .map_or(false, |content_type| is_json(&content_type.0))
+    }
+
+    /// Checks whether the client prefers JSON over any other media type according to its
+    /// `Accept` header.
+    pub fn prefers_json(&self) -> bool {
+        let accept = match self.headers.get::<Accept>() {
+            Some(accept) => accept,
+            None => return false,
+        };
+
+        // Note: The first of several equally preferred media types wins.
+        let mut preferred = None;
+        for item in accept.iter() {
+            match preferred {
+                Some((_, quality)) if item.quality <= quality => continue,
+                _ => preferred = Some((&item.item, item.quality)),
+            }
+        }
+        preferred.map_or(false, |(media_type, _)| is_json(media_type))
+    }
+
+    /// Parses the body of the request as JSON. Fails with `400 Bad Request`.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Failure> {
+        serde_json::from_slice(&self.body).map_err(|err| {
+            debug!("Cannot parse the request body: {}", err);
+            Failure::new(StatusCode::BadRequest)
+        })
+    }
+}
+
+/// Checks whether the given media type is JSON.
+fn is_json(media_type: &mime::Mime) -> bool {
+    media_type.type_() == mime::APPLICATION && media_type.subtype() == mime::JSON
+}
+
+/// The fields of a URL-encoded form, e.g. a query string.
+#[derive(Debug)]
+pub struct Fields(HashMap<String, String>);
+
+impl Fields {
+    /// Parses the given form. Fails if it contains any field other than the given ones.
+    pub fn parse(form: &str, names: &[&str]) -> Option<Self> {
+        let mut fields = HashMap::new();
+        for (name, value) in form_urlencoded::parse(form.as_bytes()) {
+            if !names.contains(&&*name) {
+                return None;
+            }
+            fields.insert(name.into_owned(), value.into_owned());
+        }
+        Some(Fields(fields))
+    }
+
+    /// Returns the value of the given field. A value that cannot be parsed is treated as missing.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.0.get(name).and_then(|value| value.parse().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_parse() {
+        let names = &["domain", "ipv4", "user", "sig"];
+        let fields = Fields::parse("domain=a%2Eexample.org&ipv4=x&user=b+c", names).unwrap();
+        assert_eq!(fields.get("domain"), Some("a.example.org".to_string()));
+        assert_eq!(fields.get::<::std::net::Ipv4Addr>("ipv4"), None);
+        assert_eq!(fields.get("user"), Some("b c".to_string()));
+        assert_eq!(fields.get::<String>("sig"), None);
+
+        assert!(Fields::parse("domain=example.org&unknown=1", &["domain"]).is_none());
+    }
+}
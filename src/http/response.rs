@@ -0,0 +1,66 @@
+//! Responses
+//!
+//! A route returns any value that implements [`Responder`], e.g. a `String`, which is sent as a
+//! plain text response, or a [`Json`] value.
+//!
+//! [`Responder`]: trait.Responder.html
+//! [`Json`]: struct.Json.html
+
+use http::errors::catch;
+
+use hyper::StatusCode;
+use hyper::header::{ContentLength, ContentType};
+use hyper::server::Response;
+use serde::Serialize;
+use serde_json;
+
+/// A value that can be turned into a response.
+pub trait Responder {
+    /// Turns the value into a response.
+    fn respond(self) -> Response;
+}
+
+impl Responder for () {
+    fn respond(self) -> Response {
+        Response::new().with_header(ContentLength(0))
+    }
+}
+
+impl Responder for &'static str {
+    fn respond(self) -> Response {
+        text(self.to_string())
+    }
+}
+
+impl Responder for String {
+    fn respond(self) -> Response {
+        text(self)
+    }
+}
+
+/// A value sent (or received) as JSON.
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> Responder for Json<T> {
+    fn respond(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => Response::new()
+                .with_header(ContentType::json())
+                .with_header(ContentLength(body.len() as u64))
+                .with_body(body),
+            Err(err) => {
+                error!("Cannot serialize a response: {}", err);
+                catch(StatusCode::InternalServerError)
+            },
+        }
+    }
+}
+
+/// Returns a plain text response with the given body.
+pub fn text(body: String) -> Response {
+    Response::new()
+        .with_header(ContentType::plaintext())
+        .with_header(ContentLength(body.len() as u64))
+        .with_body(body)
+}
@@ -1,76 +1,735 @@
-use config::Config;
-use types::Domain;
+use audit::Event;
+use config::{Config, User};
+use dns::Outcome;
+use errors::{Error, ErrorKind};
+use history::{Effect, Entry};
+use http::errors::Failure;
+use http::limit::Limiter;
+use http::request::{Fields, Request};
+use http::response::{Json, Responder};
+use http::service::{reject, Answer, Context};
+use sign::Signed;
+use types::{Domain, Rcode};
 
-use hyper::header::{Authorization, Basic, Header};
-use rocket::{Outcome, State};
-use rocket::http::Status;
-use rocket::response::Failure;
-use rocket::request::{self, FromRequest, Request};
-use std::net::{Ipv4Addr, Ipv6Addr};
+use futures::{future, stream, Future, Stream};
+use hyper::StatusCode;
+use hyper::header::{Authorization, Basic};
+use hyper::server::Response;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use tokio_core::reactor::{Handle, Timeout};
 
-#[get("/dns/update?<update>")]
-pub fn update(config: State<Config>, creds: Credentials, update: Update) -> Result<(), Failure> {
-    // Verify the credentials
-    let user = config.user(&creds.user).ok_or(Failure(Status::Unauthorized))?;
-    if !user.pw.is(&creds.pw) {
-        return Err(Failure(Status::Unauthorized));
+pub fn update(ctx: Arc<Context>, req: Request) -> Answer {
+    let update = match req.query() {
+        Some(query) => Update::from_query(query),
+        None => return reject(StatusCode::NotFound),
+    };
+    let pass = match ctx.shutdown.enter() {
+        Some(pass) => pass,
+        None => return reject(StatusCode::ServiceUnavailable),
+    };
+    let creds = Credentials::from_request(&req);
+    let format = Format::from_request(&req);
+    let update = match update {
+        Some(update) => update,
+        None => return reject(StatusCode::NotFound),
+    };
+    let config = ctx.shared.get();
+    let handle = req.handle().clone();
+    let ip = req.remote().ip();
+
+    // Determine the IP addresses
+    // Note: A signed update without a fixed IP address uses the client's IP address.
+    let (mut ipv4, mut ipv6) = (update.ipv4, update.ipv6);
+    if update.sig.is_some() && ipv4.is_none() && ipv6.is_none() {
+        match ip {
+            IpAddr::V4(addr_v4) => ipv4 = Some(addr_v4),
+            IpAddr::V6(addr_v6) => ipv6 = Some(addr_v6),
+        }
     }
+    let change = Change { domain: update.domain.clone(), ipv4, ipv6 };
+    let claimed = match (update.sig.as_ref(), creds.as_ref()) {
+        (Some(_), _) => update.user.clone(),
+        (None, Some(creds)) => Some(creds.user.clone()),
+        (None, None) => None,
+    };
 
-    // Check the authorization
-    user.domains.get(&update.domain).ok_or(Failure(Status::Forbidden))?;
+    // Check the client's address, then verify either the signature or the credentials
+    let admitted = match claimed {
+        Some(ref name) => admit(&config, name, ip).map_err(|failure| (name.clone(), failure)),
+        None => Ok(()),
+    };
+    let login: Box<Future<Item = String, Error = Failure>> =
+        match (admitted, update.sig.clone(), creds) {
+            (Err((name, failure)), _, _) => deny(&ctx, &name, ip, failure),
+            (Ok(()), Some(sig), _) => {
+                let (ctx, config) = (ctx.clone(), config.clone());
+                let pool = ctx.pool.clone();
+                Box::new(pool.spawn_fn(move || verify(&ctx, &config, &update, &sig, ip)))
+            },
+            (Ok(()), None, Some(creds)) => {
+                authenticate(ctx.clone(), config.clone(), creds, ip, &handle)
+            },
+            (Ok(()), None, None) => reject(StatusCode::Unauthorized),
+        };
 
-    // Perform the update
-    let dns = ::dns::Updater::new(config.dns.socket, config.dns.ttl).unwrap();  // TODO rm unwrap!
-    if let Some(ipv4) = update.ipv4 {
-        dns.update(update.domain.clone(), ipv4.into()).unwrap();    // TODO rm unwrap!
+    let checked = login.and_then(move |name| -> Box<Future<Item = _, Error = _>> {
+        let user = match settings(&config, &name) {
+            Ok(user) => user,
+            Err(failure) => return reject(failure),
+        };
+
+        // Check the authorization and enforce the rate limits
+        let checked = check(user, &change);
+        if let Err(failure) = checked {
+            let denied = deny(&ctx, &name, ip, failure);
+            let changes = vec![change];
+            return Box::new(denied.or_else(move |failure| refuse(ctx, name, ip, changes, failure)));
+        }
+        let limited = limit(&ctx.limiter, &name, &[&change.domain]);
+        if let Err(failure) = limited {
+            return refuse(ctx, name, ip, vec![change], failure);
+        }
+
+        // Perform the update
+        apply(ctx, &handle, &config, name, ip, vec![change])
+    });
+    let reply = checked.map(move |records| match format {
+        Format::Text => match records.into_iter().filter_map(|record| record.failure).next() {
+            Some(failure) => failure.respond(),
+            None => ().respond(),
+        },
+        Format::Json => Json(records).respond(),
+    });
+
+    // Note: The update is in progress until the response is ready.
+    Box::new(reply.then(move |result| {
+        drop(pass);
+        result
+    }))
+}
+
+pub fn update_json(ctx: Arc<Context>, req: Request) -> Answer {
+    if !req.is_json() {
+        return reject(StatusCode::NotFound);
     }
-    if let Some(ipv6) = update.ipv6 {
-        dns.update(update.domain, ipv6.into()).unwrap();    // TODO rm unwrap!
+    let pass = match ctx.shutdown.enter() {
+        Some(pass) => pass,
+        None => return reject(StatusCode::ServiceUnavailable),
+    };
+    let creds = match Credentials::from_request(&req) {
+        Some(creds) => creds,
+        None => return reject(StatusCode::Unauthorized),
+    };
+    let changes: Vec<Change> = match req.json() {
+        Ok(changes) => changes,
+        Err(failure) => return reject(failure),
+    };
+    let config = ctx.shared.get();
+    let handle = req.handle().clone();
+    let ip = req.remote().ip();
+
+    // Check the client's address before verifying the credentials
+    let login = match admit(&config, &creds.user, ip) {
+        Ok(()) => authenticate(ctx.clone(), config.clone(), creds, ip, &handle),
+        Err(failure) => deny(&ctx, &creds.user, ip, failure),
+    };
+
+    let checked = login.and_then(move |name| -> Box<Future<Item = _, Error = _>> {
+        let user = match settings(&config, &name) {
+            Ok(user) => user,
+            Err(failure) => return reject(failure),
+        };
+
+        // Check the authorization for all changes before performing any of them
+        let checked = changes.iter().map(|change| check(user, change)).collect::<Result<(), _>>();
+        if let Err(failure) = checked {
+            let denied = deny(&ctx, &name, ip, failure);
+            return Box::new(denied.or_else(move |failure| refuse(ctx, name, ip, changes, failure)));
+        }
+        let limited = {
+            let domains: Vec<&Domain> = changes.iter().map(|change| &change.domain).collect();
+            limit(&ctx.limiter, &name, &domains)
+        };
+        if let Err(failure) = limited {
+            return refuse(ctx, name, ip, changes, failure);
+        }
+
+        // Perform the updates
+        apply(ctx, &handle, &config, name, ip, changes)
+    });
+    let reply = checked.map(|records| Json(records).respond());
+
+    // Note: The updates are in progress until the response is ready.
+    Box::new(reply.then(move |result| {
+        drop(pass);
+        result
+    }))
+}
+
+pub fn status(ctx: Arc<Context>, req: Request) -> Answer {
+    let query = match req.query() {
+        Some(query) => Query::from_query(query),
+        None => return reject(StatusCode::NotFound),
+    };
+    let creds = match Credentials::from_request(&req) {
+        Some(creds) => creds,
+        None => return reject(StatusCode::Unauthorized),
+    };
+    let format = Format::from_request(&req);
+    let query = match query {
+        Some(query) => query,
+        None => return reject(StatusCode::NotFound),
+    };
+    let config = ctx.shared.get();
+    let handle = req.handle().clone();
+    let ip = req.remote().ip();
+
+    // Check the client's address before verifying the credentials
+    if let Err(failure) = admit(&config, &creds.user, ip) {
+        return deny(&ctx, &creds.user, ip, failure);
+    }
+    let login = authenticate(ctx.clone(), config.clone(), creds, ip, &handle);
+
+    let checked = login.and_then(move |name| -> Box<Future<Item = _, Error = _>> {
+        // Check the authorization
+        let user = match settings(&config, &name) {
+            Ok(user) => user,
+            Err(failure) => return reject(failure),
+        };
+        if let Err(failure) = check_domain(user, &query.domain) {
+            return deny(&ctx, &name, ip, failure);
+        }
+
+        // Query the records currently served and look up the last update
+        // Note: Looking up the last update may read the history file, hence the thread pool.
+        let dns = ctx.backend.connect(config.dns.socket, config.dns.ttl, &handle);
+        let served = dns.query(query.domain.clone()).map_err(|err| failure(&err));
+        let domain = query.domain.clone();
+        let last = served.and_then(move |ips| {
+            let pool = ctx.pool.clone();
+            pool.spawn_fn(move || -> Result<_, Failure> {
+                let last = ctx.history.as_ref().and_then(|history| history.last(&domain));
+                Ok((ips, last))
+            })
+        });
+        Box::new(last.map(move |(ips, last)| {
+            Report {
+                last_update: last.map(|entry| LastUpdate {
+                    time: entry.time,
+                    source: entry.source,
+                    user: entry.user,
+                }),
+                domain: query.domain,
+                records: ips.into_iter().map(|ip| Served { rr_type: rr_type(ip), ip }).collect(),
+                format,
+            }.respond()
+        }))
+    });
+    Box::new(checked)
+}
+
+/// Verifies the given credentials and returns the user's name.
+///
+/// Verifying a password takes a while on purpose, thus it is done by the thread pool instead of
+/// the event loop. A failed login is answered after a delay that is applied by a timer on the
+/// event loop.
+fn authenticate(
+    ctx: Arc<Context>,
+    config: Arc<Config>,
+    creds: Credentials,
+    ip: IpAddr,
+    handle: &Handle,
+) -> Box<Future<Item = String, Error = Failure>> {
+    // Refuse to verify the credentials during a lockout
+    if let Some(wait) = ctx.lockout.check(&creds.user, ip) {
+        return reject(
+            Failure::new(StatusCode::TooManyRequests)
+                .with_reason("Too many failed logins")
+                .retry_after(wait)
+        );
+    }
+
+    let pool = ctx.pool.clone();
+    let delay = ctx.lockout.delay();
+    let handle = handle.clone();
+    let verified = pool.spawn_fn(move || -> Result<_, Failure> {
+        match config.user(&creds.user) {
+            Some(user) if ctx.cache.verify(&creds.user, &creds.pw, &user.pw) => {
+                audit_event(&ctx, Event::Login {
+                    user: creds.user.clone(),
+                    source: ip,
+                    success: true,
+                });
+                info!("User '{}' logged in from '{}'", creds.user, ip);
+                ctx.lockout.succeed(&creds.user);
+                ctx.rehasher.rehash(&creds.user, &creds.pw, &user.pw);
+                Ok(Some(creds.user))
+            },
+            _ => {
+                audit_event(&ctx, Event::Login {
+                    user: creds.user.clone(),
+                    source: ip,
+                    success: false,
+                });
+                ctx.metrics.auth_failure("password");
+                warn!("Failed login of user '{}' from '{}'", creds.user, ip);
+                ctx.lockout.fail(&creds.user, ip);
+                Ok(None)
+            },
+        }
+    });
+
+    // Slow down the client after a failed login
+    Box::new(verified.and_then(move |name| match name {
+        Some(name) => future::Either::A(future::ok(name)),
+        None => future::Either::B(
+            future::result(Timeout::new(delay, &handle))
+                .flatten()
+                .then(|_| Err(Failure::new(StatusCode::Unauthorized))),
+        ),
+    }))
+}
+
+/// Verifies the signature of the given update and returns the user's name.
+fn verify(ctx: &Context, config: &Config, update: &Update, sig: &str, ip: IpAddr)
+    -> Result<String, Failure>
+{
+    let signed = update.signed().ok_or(Failure::new(StatusCode::BadRequest))?;
+    let user = config.user(&signed.user).ok_or(Failure::new(StatusCode::Forbidden))?;
+    let secret = user.secret.as_ref().ok_or(Failure::new(StatusCode::Forbidden))?;
+    let success = signed.verify(secret, sig) && !signed.is_expired();
+    audit_event(ctx, Event::Login {
+        user: signed.user.clone(),
+        source: ip,
+        success,
+    });
+    if !success {
+        ctx.metrics.auth_failure("signature");
+        warn!("Invalid or expired signature of user '{}' from '{}'", signed.user, ip);
+        return Err(Failure::new(StatusCode::Forbidden));
+    }
+    info!("Valid signature of user '{}' from '{}'", signed.user, ip);
+    Ok(signed.user)
+}
+
+/// Returns the settings of the given user, who has just logged in.
+fn settings<'c>(config: &'c Config, name: &str) -> Result<&'c User, Failure> {
+    // Note: The user cannot vanish since the configuration does not change during a request.
+    config.user(name).ok_or(Failure::new(StatusCode::Unauthorized))
+}
+
+/// Checks whether the user with the given name, if any, may send requests from the given address.
+///
+/// This check happens before the credentials are verified, so that a client cannot tell valid
+/// credentials from invalid ones by the response, and no password is verified in vain. A failed
+/// check is to be recorded as a denial, see [`deny`].
+///
+/// [`deny`]: fn.deny.html
+fn admit(config: &Config, name: &str, ip: IpAddr) -> Result<(), Failure> {
+    match config.user(name) {
+        Some(user) => check_client(user, ip),
+        None => Ok(()),
+    }
+}
+
+/// Checks whether the user may send requests from the given address.
+fn check_client(user: &User, ip: IpAddr) -> Result<(), Failure> {
+    if !user.allows_client(ip) {
+        return Err(
+            Failure::new(StatusCode::Forbidden)
+                .with_reason(format!("The user may not send requests from '{}'", ip))
+        );
+    }
+    Ok(())
+}
+
+/// Checks whether the user is authorized for the given domain.
+fn check_domain(user: &User, domain: &Domain) -> Result<(), Failure> {
+    if user.domains.get(domain).is_none() {
+        return Err(
+            Failure::new(StatusCode::Forbidden)
+                .with_reason(format!("The user may not update '{}'", domain))
+        );
+    }
+    Ok(())
+}
+
+/// Checks whether the user may perform the given change.
+fn check(user: &User, change: &Change) -> Result<(), Failure> {
+    // Check the authorization
+    check_domain(user, &change.domain)?;
+
+    // Check the IP addresses against the user's policy
+    for ip in change.ips() {
+        if !user.allows_target(ip) {
+            return Err(
+                Failure::new(StatusCode::UnprocessableEntity)
+                    .with_reason(format!("The user may not point domains to '{}'", ip))
+            );
+        }
     }
 
     Ok(())
 }
 
+/// Enforces the rate limits for updates of the given domains by the given user.
+fn limit(limiter: &Limiter, name: &str, domains: &[&Domain]) -> Result<(), Failure> {
+    limiter.take_all(name, domains).map_err(|wait| {
+        Failure::new(StatusCode::TooManyRequests)
+            .with_reason("Too many updates")
+            .retry_after(wait)
+    })
+}
+
+/// Performs the given changes on behalf of the given user and returns the outcome for every
+/// record. Every attempt is recorded in the history, in the audit log and in the metrics.
+///
+/// The records are updated one after another. Recording an attempt writes to files, thus it is
+/// done by the thread pool instead of the event loop.
+fn apply(
+    ctx: Arc<Context>,
+    handle: &Handle,
+    config: &Config,
+    name: String,
+    source: IpAddr,
+    changes: Vec<Change>,
+) -> Box<Future<Item = Vec<Record>, Error = Failure>> {
+    let dns = ctx.backend.connect(config.dns.socket, config.dns.ttl, handle);
+    let mut updates = Vec::new();
+    for change in changes {
+        for ip in change.ips() {
+            updates.push((change.domain.clone(), ip));
+        }
+    }
+
+    let records = stream::iter_ok::<_, Failure>(updates).and_then(move |(domain, ip)| {
+        let ctx = ctx.clone();
+        let name = name.clone();
+        dns.update(domain.clone(), ip).then(move |result| {
+            let record = Record::new(domain, ip, result);
+            let pool = ctx.pool.clone();
+            pool.spawn_fn(move || -> Result<_, Failure> {
+                record_update(&ctx, &name, source, &record);
+                Ok(record)
+            })
+        })
+    });
+    Box::new(records.collect())
+}
+
+/// Records the given attempt to update a record on behalf of the given user in the history, in the
+/// audit log and in the metrics.
+fn record_update(ctx: &Context, name: &str, source: IpAddr, record: &Record) {
+    audit_event(ctx, Event::Update {
+        user: name.into(),
+        source,
+        domain: record.domain.clone(),
+        ip: record.ip,
+        result: record.result,
+        rcode: record.rcode,
+    });
+    let entry = Entry {
+        rcode: record.rcode,
+        reason: record.reason.clone(),
+        ..Entry::new(name, source, record.domain.clone(), record.ip, record.result)
+    };
+    ctx.metrics.update(record.result);
+    if record.result.is_success() {
+        ctx.metrics.last_update(&record.domain, entry.time);
+    }
+    if let Some(ref history) = ctx.history {
+        if let Err(err) = history.append(entry) {
+            error!("Cannot record the update of '{}': {}", record.domain, err.describe());
+        }
+    }
+}
+
+/// Records the rejection of the given changes requested by the given user in the history and in
+/// the metrics, e.g. because of a missing authorization or an exceeded rate limit, and fails with
+/// the given failure afterwards. Only rejections of users who have logged in are recorded; failed
+/// logins are recorded in the audit log instead, see [`authenticate`].
+///
+/// Recording a rejection writes to the history file, thus it is done by the thread pool instead of
+/// the event loop.
+///
+/// [`authenticate`]: fn.authenticate.html
+fn refuse<T>(
+    ctx: Arc<Context>,
+    name: String,
+    source: IpAddr,
+    changes: Vec<Change>,
+    failure: Failure,
+) -> Box<Future<Item = T, Error = Failure>>
+    where T: Send + 'static
+{
+    let reason = match failure.reason() {
+        Some(reason) => format!("{}: {}", failure.status(), reason),
+        None => failure.status().to_string(),
+    };
+    let pool = ctx.pool.clone();
+    Box::new(pool.spawn_fn(move || {
+        for change in &changes {
+            for ip in change.ips() {
+                ctx.metrics.update(Effect::Rejected);
+                if let Some(ref history) = ctx.history {
+                    let entry = Entry {
+                        reason: Some(reason.clone()),
+                        ..Entry::new(&name, source, change.domain.clone(), ip, Effect::Rejected)
+                    };
+                    if let Err(err) = history.append(entry) {
+                        let domain = &change.domain;
+                        error!("Cannot record the rejection of '{}': {}", domain, err.describe());
+                    }
+                }
+            }
+        }
+        Err(failure)
+    }))
+}
+
+/// Records the denial of a request by the given user in the audit log and fails with the given
+/// failure afterwards.
+///
+/// Recording a denial writes to the audit log file, thus it is done by the thread pool instead of
+/// the event loop.
+fn deny<T>(ctx: &Arc<Context>, name: &str, source: IpAddr, failure: Failure)
+    -> Box<Future<Item = T, Error = Failure>>
+    where T: Send + 'static
+{
+    let reason = failure.reason().unwrap_or("Forbidden").to_string();
+    warn!("Denied a request of user '{}' from '{}': {}", name, source, reason);
+    let event = Event::Denied { user: name.into(), source, reason };
+    let ctx = ctx.clone();
+    let pool = ctx.pool.clone();
+    Box::new(pool.spawn_fn(move || {
+        audit_event(&ctx, event);
+        Err(failure)
+    }))
+}
+
+/// Notifies the hooks about the given event and records it in the audit log.
+fn audit_event(ctx: &Context, event: Event) {
+    ctx.hooks.notify(&event);
+    if let Err(err) = ctx.audit.record(event) {
+        error!("Cannot record an audit event: {}", err.describe());
+    }
+}
+
+/// Turns an error during an update into a failure that explains its cause.
+///
+/// A DNS server that refuses the update (`REFUSED`, `NOTAUTH`, `NOTZONE`) leads to
+/// `403 Forbidden`, a DNS server that does not respond in time leads to `504 Gateway Timeout` and
+/// any other misbehavior of the DNS server leads to `502 Bad Gateway`.
+fn failure(err: &Error) -> Failure {
+    let status = match err.rcode() {
+        _ if err.is_timeout() => StatusCode::GatewayTimeout,
+        Some(Rcode::REFUSED) | Some(Rcode::NOTAUTH) | Some(Rcode::NOTZONE) => StatusCode::Forbidden,
+        Some(_) => StatusCode::BadGateway,
+        None => match *err.kind() {
+            ErrorKind::DnsDomainConvert(_) => StatusCode::InternalServerError,
+            _ => StatusCode::BadGateway,
+        },
+    };
+    Failure::new(status).with_reason(err.describe())
+}
+
+/// The format of a response, as preferred by the client's `Accept` header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    /// Determines the format preferred by the client.
+    pub fn from_request(req: &Request) -> Self {
+        if req.prefers_json() { Format::Json } else { Format::Text }
+    }
+}
+
+/// A change of a domain, i.e. the IP addresses it should point to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Change {
+    domain: Domain,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+}
+
+impl Change {
+    /// Returns the IP addresses of the change.
+    fn ips(&self) -> Vec<IpAddr> {
+        self.ipv4.map(IpAddr::V4).into_iter().chain(self.ipv6.map(IpAddr::V6)).collect()
+    }
+}
+
+/// The outcome of an update of a single record.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    domain: Domain,
+    #[serde(rename = "type")]
+    rr_type: &'static str,
+    ip: IpAddr,
+    result: Effect,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rcode: Option<Rcode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing)]
+    failure: Option<Failure>,
+}
+
+impl Record {
+    fn new(domain: Domain, ip: IpAddr, result: ::errors::Result<Outcome>) -> Self {
+        let (result, rcode, reason, failure) = match result {
+            Ok(Outcome::Changed) => (Effect::Changed, None, None, None),
+            Ok(Outcome::Unchanged) => (Effect::Unchanged, None, None, None),
+            Err(err) => {
+                let reason = err.describe();
+                error!("Cannot update '{}' to '{}': {}", domain, ip, reason);
+                (Effect::Failed, err.rcode(), Some(reason), Some(failure(&err)))
+            },
+        };
+        Record {
+            domain,
+            rr_type: rr_type(ip),
+            ip,
+            result,
+            rcode,
+            reason,
+            failure,
+        }
+    }
+}
+
+/// Returns the type of the record that holds the given IP address.
+fn rr_type(ip: IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    }
+}
+
+/// A record currently served by the DNS server.
+#[derive(Debug, Serialize)]
+pub struct Served {
+    #[serde(rename = "type")]
+    rr_type: &'static str,
+    ip: IpAddr,
+}
+
+/// The status of a domain, i.e. the records currently served and the last successful update.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    domain: Domain,
+    records: Vec<Served>,
+    last_update: Option<LastUpdate>,
+    #[serde(skip_serializing)]
+    format: Format,
+}
+
+/// The last successful update of a domain.
+#[derive(Debug, Serialize)]
+pub struct LastUpdate {
+    time: u64,
+    source: IpAddr,
+    user: String,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for record in &self.records {
+            writeln!(f, "{} {} {}", self.domain, record.rr_type, record.ip)?;
+        }
+        match self.last_update {
+            Some(ref last) => writeln!(
+                f,
+                "Last update at {} by '{}' from '{}'",
+                last.time,
+                last.user,
+                last.source,
+            ),
+            None => writeln!(f, "No update recorded"),
+        }
+    }
+}
+
+impl Responder for Report {
+    fn respond(self) -> Response {
+        match self.format {
+            Format::Text => self.to_string().respond(),
+            Format::Json => Json(self).respond(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Credentials {
     user: String,
     pw: String
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for Credentials {
-    type Error = ();    // TODO May use a better error!
-
-    fn from_request(req: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        // Extract base64-encoded HTTP Authorization header, if existent
-        if let Some(auth) = req
-            .headers()
-            .get_one("Authorization")
-        {
-            // Decode HTTP Authorization header
-            if let
-                Ok(
-                    Authorization(
-                        Basic {
-                            username: user,
-                            password: Some(pw)
-                        }
-                    )
-                )
-            = Authorization::<Basic>::parse_header(&auth.into()) {
-                return Outcome::Success(
-                    Self { user, pw }
-                )
-            }
+impl Credentials {
+    /// Extracts the credentials from the `Authorization` header, if it holds both a user name and
+    /// a password.
+    fn from_request(req: &Request) -> Option<Self> {
+        match req.headers().get::<Authorization<Basic>>() {
+            Some(&Authorization(Basic { ref username, password: Some(ref pw) })) => {
+                Some(Credentials { user: username.clone(), pw: pw.clone() })
+            },
+            _ => None,
         }
-
-        // If anything goes wrong, fail with 401 Unauthorized
-        Outcome::Failure((Status::Unauthorized, ()))
     }
 }
 
-#[derive(Debug, FromForm)]
+#[derive(Debug)]
 pub struct Update {
     domain: Domain,
     ipv4: Option<Ipv4Addr>,
     ipv6: Option<Ipv6Addr>,
+
+    // Parameters of a pre-signed update URL
+    user: Option<String>,
+    expires: Option<u64>,
+    sig: Option<String>,
+}
+
+impl Update {
+    /// Parses the query string of an update request.
+    fn from_query(query: &str) -> Option<Self> {
+        let fields = Fields::parse(query, &["domain", "ipv4", "ipv6", "user", "expires", "sig"])?;
+        Some(Update {
+            domain: fields.get("domain")?,
+            ipv4: fields.get("ipv4"),
+            ipv6: fields.get("ipv6"),
+            user: fields.get("user"),
+            expires: fields.get("expires"),
+            sig: fields.get("sig"),
+        })
+    }
+
+    /// Returns the signed parameters, if all of them are present.
+    fn signed(&self) -> Option<Signed> {
+        Some(Signed {
+            user: self.user.clone()?,
+            domain: self.domain.clone(),
+            ipv4: self.ipv4,
+            ipv6: self.ipv6,
+            expires: self.expires?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Query {
+    domain: Domain,
+}
+
+impl Query {
+    /// Parses the query string of a status request.
+    fn from_query(query: &str) -> Option<Self> {
+        let fields = Fields::parse(query, &["domain"])?;
+        Some(Query { domain: fields.get("domain")? })
+    }
 }
@@ -0,0 +1,76 @@
+use http::errors::Failure;
+use http::probe::Target;
+use http::request::Request;
+use http::response::{Json, Responder};
+use http::routes::dns::Format;
+use http::service::{answer, reject, Answer, Context};
+
+use futures::Future;
+use hyper::StatusCode;
+use hyper::server::Response;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+pub fn health(_: Arc<Context>, _: Request) -> Answer {
+    answer("OK")
+}
+
+pub fn ready(ctx: Arc<Context>, req: Request) -> Answer {
+    // Note: A stopping server is not ready, no matter whether the DNS server is reachable.
+    if ctx.shutdown.is_stopping() {
+        return reject(StatusCode::ServiceUnavailable);
+    }
+    let format = Format::from_request(&req);
+    let readiness = ctx.probe
+        .check(&*ctx.backend, req.handle(), &[ctx.shared.get().dns.socket])
+        .map_err(|()| Failure::new(StatusCode::InternalServerError))
+        .map(move |targets| {
+            let ready = targets.iter().all(|target| target.ready);
+            Readiness { ready, targets, format }.respond()
+        });
+    Box::new(readiness)
+}
+
+/// The readiness of the server, i.e. whether every DNS server is reachable.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    ready: bool,
+    targets: Vec<Target>,
+    #[serde(skip_serializing)]
+    format: Format,
+}
+
+impl Display for Readiness {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", if self.ready { "Ready" } else { "Not ready" })?;
+        for target in &self.targets {
+            match (target.rcode, target.reason.as_ref()) {
+                (Some(rcode), _) => writeln!(
+                    f,
+                    "DNS server '{}' responded with '{}' after {} ms",
+                    target.socket,
+                    rcode,
+                    target.duration,
+                )?,
+                (None, reason) => writeln!(
+                    f,
+                    "DNS server '{}' is not reachable: {}",
+                    target.socket,
+                    reason.map_or("unknown reason", String::as_str),
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Responder for Readiness {
+    fn respond(self) -> Response {
+        let status = if self.ready { StatusCode::Ok } else { StatusCode::ServiceUnavailable };
+        let response = match self.format {
+            Format::Text => self.to_string().respond(),
+            Format::Json => Json(self).respond(),
+        };
+        response.with_status(status)
+    }
+}
@@ -1,24 +1,30 @@
 pub mod dns;
+pub mod health;
+
+use http::request::Request;
+use http::service::{answer, Answer, Context};
 
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
-#[get("/ip")]
-pub fn ip(addr: SocketAddr) -> String {
-    match addr.ip() {
+pub fn ip(_: Arc<Context>, req: Request) -> Answer {
+    answer(match req.remote().ip() {
         IpAddr::V4(addr_v4) => format!("{}", addr_v4),
         IpAddr::V6(addr_v6) => format!("{}", addr_v6),
-    }
+    })
 }
 
-#[get("/port")]
-pub fn port(addr: SocketAddr) -> String {
-    format!("{}", addr.port())
+pub fn port(_: Arc<Context>, req: Request) -> Answer {
+    answer(format!("{}", req.remote().port()))
 }
 
-#[get("/socket")]
-pub fn socket(addr: SocketAddr) -> String {
-    match addr {
+pub fn socket(_: Arc<Context>, req: Request) -> Answer {
+    answer(match req.remote() {
         SocketAddr::V4(addr_v4) => format!("{}", addr_v4),
         SocketAddr::V6(addr_v6) => format!("{}", addr_v6),
-    }
+    })
+}
+
+pub fn metrics(ctx: Arc<Context>, _: Request) -> Answer {
+    answer(ctx.metrics.to_string())
 }
@@ -0,0 +1,206 @@
+//! Request dispatching
+//!
+//! The HTTP server runs on an event loop. Every connection is served by a [`Service`] that
+//! receives the body of a request, dispatches the request to the matching [`Route`] and turns a
+//! [`Failure`] into an error response.
+//!
+//! Routes are ordinary functions (or closures). The state they share, e.g. the configuration, is
+//! held by a [`Context`]. Expensive or blocking work, i.e. verifying passwords and writing to the
+//! history or the audit log, is handed over to a thread pool, so that it never blocks the event
+//! loop.
+//!
+//! [`Service`]: struct.Service.html
+//! [`Route`]: struct.Route.html
+//! [`Failure`]: ../errors/struct.Failure.html
+//! [`Context`]: struct.Context.html
+
+use audit::Trail;
+use config::Shared;
+use dns::Backend;
+use history::History;
+use http::cache::Cache;
+use http::errors::Failure;
+use http::hooks::Hooks;
+use http::limit::Limiter;
+use http::listen::{self, Peer};
+use http::lockout::Tracker;
+use http::probe::Probe;
+use http::rehash::Rehasher;
+use http::request::{Request, BODY_LIMIT};
+use http::response::Responder;
+use http::shutdown::Shutdown;
+use metrics::Registry;
+
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use futures_cpupool::CpuPool;
+use hyper::{self, Body, Method, StatusCode};
+use hyper::server::{self, Response};
+use std::rc::Rc;
+use std::sync::Arc;
+use tokio_core::reactor::Handle;
+
+/// The eventual response of a route.
+pub type Answer = Box<Future<Item = Response, Error = Failure>>;
+
+/// The state shared by all routes.
+#[derive(Debug)]
+pub struct Context {
+    pub shared: Shared,
+    pub cache: Cache,
+    pub lockout: Tracker,
+    pub rehasher: Rehasher,
+    pub limiter: Limiter,
+    pub probe: Probe,
+    pub history: Option<History>,
+    pub audit: Trail,
+    pub hooks: Hooks,
+    pub metrics: Arc<Registry>,
+    pub shutdown: Shutdown,
+
+    /// The backend performing the DNS operations.
+    pub backend: Arc<Backend>,
+
+    /// The thread pool for expensive work, e.g. verifying passwords.
+    pub pool: CpuPool,
+}
+
+/// A route, i.e. a function handling the requests with a certain method and path.
+///
+/// A route for `GET` requests also handles `HEAD` requests.
+pub struct Route {
+    method: Method,
+    path: &'static str,
+    handler: Box<Fn(Arc<Context>, Request) -> Answer + Send>,
+}
+
+impl Route {
+    /// Creates a new route.
+    pub fn new<F>(method: Method, path: &'static str, handler: F) -> Self
+        where F: Fn(Arc<Context>, Request) -> Answer + Send + 'static
+    {
+        Route { method, path, handler: Box::new(handler) }
+    }
+
+    /// Checks whether the route handles the given request.
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        let head = *method == Method::Head && self.method == Method::Get;
+        (*method == self.method || head) && path == self.path
+    }
+}
+
+/// A set of routes sharing a context.
+pub struct Router {
+    routes: Vec<Route>,
+    context: Arc<Context>,
+}
+
+impl Router {
+    /// Creates a new router.
+    pub fn new(routes: Vec<Route>, context: Arc<Context>) -> Self {
+        Router { routes, context }
+    }
+
+    /// Returns the context shared by the routes.
+    pub fn context(&self) -> &Arc<Context> {
+        &self.context
+    }
+
+    /// Dispatches the given request to the matching route.
+    fn dispatch(&self, req: Request) -> Answer {
+        match self.routes.iter().find(|route| route.matches(req.method(), req.path())) {
+            Some(route) => (route.handler)(self.context.clone(), req),
+            None => reject(StatusCode::NotFound),
+        }
+    }
+}
+
+/// The service of a single connection.
+pub struct Service {
+    router: Rc<Router>,
+    peer: Peer,
+    handle: Handle,
+}
+
+impl Service {
+    /// Creates a new service for a connection from the given peer.
+    pub fn new(router: Rc<Router>, peer: Peer, handle: Handle) -> Self {
+        Service { router, peer, handle }
+    }
+}
+
+impl server::Service for Service {
+    type Request = server::Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: server::Request) -> Self::Future {
+        let (method, uri, _, headers, body) = req.deconstruct();
+        let remote = match self.peer {
+            Peer::Tcp(remote) => Some(remote),
+            Peer::Proxy(ref header) => listen::forwarded(&headers, header.as_ref().map(|h| &h[..])),
+        };
+        let header = match self.peer {
+            Peer::Proxy(Some(ref header)) => header.clone(),
+            _ => String::from("X-Forwarded-For"),
+        };
+        let router = self.router.clone();
+        let handle = self.handle.clone();
+        let line = format!("{} {}", method, uri.path());
+        let head = method == Method::Head;
+
+        // Receive the body, but not more of it than allowed
+        // Note: A body that is too large is no error of the connection, hence `None`.
+        let body = body.map_err(Some).fold(Vec::new(), |mut body, chunk| {
+            if body.len() + chunk.len() > BODY_LIMIT {
+                return Err(None);
+            }
+            body.extend_from_slice(&chunk);
+            Ok(body)
+        });
+
+        // Dispatch the request and turn a failure into an error response
+        let future = body.then(move |result| {
+            let answer = match (result, remote) {
+                // Note: Without the header, the client is unknown, hence it cannot be locked out.
+                (Ok(_), None) => {
+                    let reason = format!("Missing or invalid header '{}'", header);
+                    reject(Failure::new(StatusCode::BadRequest).with_reason(reason))
+                },
+                (Ok(body), Some(remote)) => {
+                    let path = uri.path().to_string();
+                    let query = uri.query().map(String::from);
+                    let req = Request::new(method, path, query, headers, remote, body, handle);
+                    router.dispatch(req)
+                },
+                (Err(Some(err)), _) => return Either::A(future::err(err)),
+                (Err(None), _) => reject(StatusCode::BadRequest),
+            };
+            let remote = remote.unwrap_or_else(listen::unix_peer);
+            Either::B(answer.then(move |result| -> Result<Response, hyper::Error> {
+                let mut response = result.unwrap_or_else(Failure::respond);
+                info!("{} from '{}': {}", line, remote.ip(), response.status());
+
+                // Note: The response to a `HEAD` request keeps its headers, but not its body.
+                if head {
+                    response.set_body(Body::empty());
+                }
+                Ok(response)
+            }))
+        });
+        Box::new(future)
+    }
+}
+
+/// Returns an answer that responds with the given value right away.
+pub fn answer<R: Responder>(responder: R) -> Answer {
+    Box::new(future::ok(responder.respond()))
+}
+
+/// Returns an eventual value that fails with the given failure right away.
+pub fn reject<T, F>(failure: F) -> Box<Future<Item = T, Error = Failure>>
+    where T: 'static, F: Into<Failure>
+{
+    Box::new(future::err(failure.into()))
+}
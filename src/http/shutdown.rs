@@ -0,0 +1,169 @@
+//! Graceful shutdown
+//!
+//! An update consists of several DNS messages. Stopping the process in the middle of an update
+//! may leave a domain without any record. Thus, when the process receives a SIGINT, SIGTERM or
+//! SIGQUIT, the server shuts down gracefully instead:
+//!
+//! 1. The sockets stop accepting connections. New update requests on connections that are still
+//!    open are refused with `503 Service Unavailable`, and so are readiness checks.
+//! 2. The server waits for the updates in progress to finish, but not longer than the configured
+//!    grace period.
+//! 3. The [`Lock`] is released and the process exits.
+//!
+//! A [`Shutdown`] keeps track of the updates in progress. An update route takes part by requiring
+//! a [`Pass`].
+//!
+//! [`Lock`]: ../../lock/struct.Lock.html
+//! [`Shutdown`]: struct.Shutdown.html
+//! [`Pass`]: struct.Pass.html
+
+use errors::*;
+use http::systemd;
+use lock::Lock;
+
+use libc::{self, c_int};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The signals that cause a graceful shutdown.
+const SIGNALS: &[c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGQUIT];
+
+/// The interval (in milliseconds) at which signals and updates in progress are checked.
+const INTERVAL: u64 = 100;
+
+/// The signal received, if any. (`0` means none.)
+static SIGNAL: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handle_signal(signal: c_int) {
+    SIGNAL.store(signal as usize, Ordering::SeqCst);
+}
+
+/// The progress of a shutdown.
+///
+/// Cloning a `Shutdown` yields another handle to the same progress.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<Progress>);
+
+/// Whether the server is stopping and how many updates are in progress.
+#[derive(Debug, Default)]
+struct Progress {
+    stopping: AtomicBool,
+    active: AtomicUsize,
+}
+
+impl Shutdown {
+    /// Creates a new `Shutdown` for a running server.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Admits a new update unless the server is stopping.
+    pub fn enter(&self) -> Option<Pass> {
+        self.0.active.fetch_add(1, Ordering::SeqCst);
+        let pass = Pass(self.clone());
+        if self.is_stopping() {
+            return None;
+        }
+        Some(pass)
+    }
+
+    /// Refuses any new update.
+    pub fn stop(&self) {
+        self.0.stopping.store(true, Ordering::SeqCst);
+    }
+
+    /// Checks whether the server is stopping.
+    pub fn is_stopping(&self) -> bool {
+        self.0.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of updates in progress.
+    pub fn active(&self) -> usize {
+        self.0.active.load(Ordering::SeqCst)
+    }
+
+    /// Waits until there is no update in progress anymore, but not longer than the given time.
+    /// Returns whether all updates finished.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.active() > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(INTERVAL));
+        }
+        true
+    }
+}
+
+/// The permission to perform an update. As long as a `Pass` exists, the update is in progress.
+#[derive(Debug)]
+pub struct Pass(Shutdown);
+
+impl Drop for Pass {
+    fn drop(&mut self) {
+        (self.0).0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Sets up the signal handlers and starts a thread that shuts the server down gracefully, waiting
+/// at most the given time (in seconds) for updates in progress.
+pub fn watch(shutdown: Shutdown, grace: u64, lock: Lock) -> Result<()> {
+    // Set up the signal handlers
+    // Note: The handler merely stores the signal since almost nothing is safe within a handler.
+    let handler = handle_signal as extern "C" fn(c_int) as libc::sighandler_t;
+    for &signal in SIGNALS {
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            bail!(ErrorKind::HttpSetupSignalHandler(signal));
+        }
+    }
+
+    thread::spawn(move || {
+        // Wait for a signal
+        let signal = loop {
+            thread::sleep(Duration::from_millis(INTERVAL));
+            match SIGNAL.load(Ordering::SeqCst) {
+                0 => continue,
+                signal => break signal,
+            }
+        };
+
+        // Refuse new updates and wait for the updates in progress
+        info!("Received signal {}, shutting down", signal);
+        systemd::notify("STOPPING=1");
+        shutdown.stop();
+        if !shutdown.wait(Duration::from_secs(grace)) {
+            warn!("Stopping while {} update(s) are still in progress", shutdown.active());
+        }
+
+        // Release the lock and exit
+        // Note: The process must exit explicitly since the event loop never returns.
+        lock.release();
+        info!("Stopped");
+        process::exit(0);
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_wait() {
+        let shutdown = Shutdown::new();
+        let pass = shutdown.enter().unwrap();
+        shutdown.stop();
+        assert!(shutdown.enter().is_none());
+        assert!(shutdown.is_stopping());
+        assert_eq!(shutdown.active(), 1);
+        assert!(!shutdown.wait(Duration::from_millis(0)));
+
+        drop(pass);
+        assert!(shutdown.wait(Duration::from_millis(0)));
+    }
+}
@@ -0,0 +1,155 @@
+//! systemd integration
+//!
+//! When running as a systemd service, the server
+//!
+//! - takes over the listening sockets passed by systemd (socket activation, see `LISTEN_FDS`)
+//!   instead of binding them itself, e.g. to listen on a privileged port without running as root,
+//! - notifies systemd as soon as it is ready (`READY=1`) and when it is stopping (`STOPPING=1`)
+//!   and
+//! - pings the watchdog (`WATCHDOG=1`), if enabled, as long as the DNS servers are reachable.
+//!
+//! Outside of systemd, i.e. if the corresponding environment variables are not set, nothing
+//! happens.
+
+use config::Shared;
+use dns::Backend;
+use errors::*;
+use http::listen::Socket;
+use http::probe::{self, Probe};
+
+use futures::{Future, Stream};
+use libc;
+use std::env;
+use std::ffi::OsStr;
+use std::{io, mem};
+use std::net::TcpListener;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Interval};
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes over the listening sockets passed by systemd, if any. These are either TCP sockets or
+/// Unix domain sockets.
+pub fn listeners() -> Result<Vec<Socket>> {
+    // Check whether there are sockets meant for this process
+    if !is_current_process("LISTEN_PID") {
+        return Ok(Vec::new());
+    }
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .ok_or(ErrorKind::SystemdListenFds)?;
+
+    // Keep child processes from taking over the sockets, too
+    for name in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            let socket = take(fd).chain_err(|| ErrorKind::SystemdSocket(fd))?;
+            info!("Taking over the socket '{}' from systemd", socket);
+            Ok(socket)
+        })
+        .collect()
+}
+
+/// Takes over the given listening socket, depending on its address family.
+fn take(fd: RawFd) -> io::Result<Socket> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => {
+            Ok(Socket::Tcp(unsafe { TcpListener::from_raw_fd(fd) }))
+        },
+        libc::AF_UNIX => {
+            let listener = unsafe { UnixListener::from_raw_fd(fd) };
+
+            // Note: An unnamed or abstract socket has no path to match a listener against.
+            let path = listener.local_addr()?.as_pathname().map(|path| path.to_path_buf());
+            match path {
+                Some(path) => Ok(Socket::Unix(listener, path, None)),
+                None => Err(io::Error::new(io::ErrorKind::Other, "Socket has no path")),
+            }
+        },
+        _ => Err(io::Error::new(io::ErrorKind::Other, "Neither a TCP nor a Unix domain socket")),
+    }
+}
+
+/// Notifies systemd about the given state, e.g. `READY=1`.
+pub fn notify(state: &str) {
+    let mut path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path.into_vec(),
+        None => return,
+    };
+
+    // Note: A leading `@` denotes an abstract socket address.
+    if path.first() == Some(&b'@') {
+        path[0] = 0;
+    }
+
+    let result = UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(state.as_bytes(), OsStr::from_bytes(&path)));
+    if let Err(err) = result {
+        warn!("Cannot notify systemd about '{}': {}", state, err);
+    }
+}
+
+/// Pings the watchdog on the event loop of the given handle, if enabled, as long as the DNS
+/// servers are reachable using the given backend.
+///
+/// The watchdog is pinged twice per timeout. If a DNS server is not reachable, the ping is
+/// skipped, so that systemd restarts the service unless the DNS server recovers in time.
+pub fn watchdog(shared: Shared, backend: Arc<Backend>, handle: &Handle) -> Result<()> {
+    if !is_current_process("WATCHDOG_PID") && env::var_os("WATCHDOG_PID").is_some() {
+        return Ok(());
+    }
+    let timeout = match env::var("WATCHDOG_USEC").ok().and_then(|usec| usec.parse::<u64>().ok()) {
+        Some(timeout) if timeout > 0 => timeout,
+        _ => return Ok(()),
+    };
+    let interval = Duration::new(timeout / 2 / 1_000_000, (timeout / 2 % 1_000_000) as u32 * 1000);
+
+    let probe = Probe::new(probe::TTL);
+    let spawner = handle.clone();
+    let pings = Interval::new_at(Instant::now(), interval, handle)
+        .chain_err(|| ErrorKind::HttpReactor)?
+        .map_err(|err| error!("The watchdog timer failed: {}", err))
+        .for_each(move |_| {
+            let targets = [shared.get().dns.socket];
+            let ping = probe.check(&*backend, &spawner, &targets).map(|targets| {
+                match targets.iter().find(|target| !target.ready) {
+                    Some(target) => warn!(
+                        "Skipping the watchdog ping since DNS server '{}' is not ready",
+                        target.socket,
+                    ),
+                    None => notify("WATCHDOG=1"),
+                }
+            });
+            spawner.spawn(ping);
+            Ok(())
+        });
+    handle.spawn(pings);
+    Ok(())
+}
+
+/// Checks whether the environment variable with the given name contains the current PID.
+fn is_current_process(name: &str) -> bool {
+    env::var(name)
+        .ok()
+        .and_then(|pid| pid.parse::<libc::pid_t>().ok())
+        .map_or(false, |pid| pid == unsafe { libc::getpid() })
+}
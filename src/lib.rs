@@ -32,37 +32,50 @@
 //! (This is actually all you need to mimic `dynonym`'s behavior since every little bit is
 //! implemented within the library.)
 //!
+//! In order to embed the web server into another application or a test, assemble an
+//! [`http::Server`] from the configuration, a DNS backend, additional routes and hooks. It runs in
+//! a background thread until you shut it down.
+//!
+//! [`http::Server`]: http/struct.Server.html
+//!
 //! ## Module structure
 //!
 //! At the top level, modules can be grouped as follows:
 //!
 //! * Modules that fulfill a certain task
 //!     * Modules that provide a remote interface
-//!         * [`http`]: Web server (incl. routes)
+//!         * [`http`]: Web server (incl. routes and an embeddable server)
 //!         * [`dns`]: Domain Name System update client (RFC 2136: "DNS UPDATE")
+//!     * Modules that provide security mechanisms
+//!         * [`audit`]: Audit log
+//!         * [`sign`]: Pre-signed, expiring update URLs
 //!     * Modules that deal with the operating system
 //!         * [`cli`]: Command-line argument parsing and instruction assembly
 //!         * [`config`]: Configuration file parsing
+//!         * [`daemon`]: Daemon mode
+//!         * [`history`]: Update history
 //!         * [`lock`]: Lock file management
+//!         * [`logging`]: Logging
+//!         * [`metrics`]: Metrics (Prometheus)
 //! * Modules that provide general support
 //!     * [`types`]: Shared types (e.g. for a domain name)
 //!     * [`errors`]: Error types and handling
 //!
+//! [`audit`]: audit/index.html
 //! [`cli`]: cli/index.html
 //! [`config`]: config/index.html
+//! [`daemon`]: daemon/index.html
 //! [`dns`]: dns/index.html
 //! [`http`]: http/index.html
 //! [`errors`]: errors/index.html
 //! [`types`]: types/index.html
 //! [`lock`]: lock/index.html
+//! [`logging`]: logging/index.html
+//! [`metrics`]: metrics/index.html
+//! [`sign`]: sign/index.html
+//! [`history`]: history/index.html
 
-#![feature(
-    custom_derive,
-    plugin,
-    try_from,
-)]
-#![plugin(rocket_codegen)]
-#![recursion_limit="256"]   // `error_chain!` can recurse deeply
+#![recursion_limit="512"]   // `error_chain!` can recurse deeply
 #![warn(
     // missing_docs,
     trivial_casts,
@@ -73,28 +86,49 @@
     unused_qualifications,
 )]
 
+extern crate argon2;
+extern crate base64;
 extern crate bcrypt;
+extern crate chrono;
 #[macro_use] extern crate clap;
-extern crate ctrlc;
 #[macro_use] extern crate error_chain;
+extern crate futures;
+extern crate futures_cpupool;
+extern crate hmac;
 extern crate hyper;
+#[macro_use] extern crate lazy_static;
 extern crate libc;
+#[macro_use] extern crate log;
+extern crate mio;
 extern crate num_cpus;
-extern crate rocket;
+extern crate rand;
 extern crate rpassword;
+extern crate serde;
 #[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
+extern crate subtle;
 #[cfg(test)] extern crate tempfile;
+extern crate tokio_core;
+extern crate tokio_io;
 extern crate toml;
 extern crate trust_dns;
 extern crate trust_dns_proto;
+extern crate url;
 extern crate yansi;
 
+pub mod audit;
 pub mod cli;
 pub mod config;
+pub mod daemon;
 pub mod dns;
 pub mod errors;
+pub mod history;
 pub mod http;
 pub mod lock;
+pub mod logging;
+pub mod metrics;
+pub mod sign;
 pub mod types;
 
 pub use cli::main;
@@ -8,95 +8,143 @@
 //! [`Lock`]: struct.Lock.html
 
 use errors::*;
+use sign::now;
 
-use std::fs::{OpenOptions, remove_file};
+use libc;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions, remove_file};
+use std::io::{self, SeekFrom};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// The file that contains a random ID of the current boot (Linux only).
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
 
 /// A `Lock` is the exclusive handle representing an existing lock file.
 ///
-/// When creating a `Lock`, a lock file is auto-created and stored at the given path. The current
-/// process id (PID) is written into the lock file. When a `Lock` is dropped (== goes
-/// out-of-scope), the lock file is removed.
+/// When creating a `Lock`, a lock file is auto-created and stored at the given path. Information
+/// about the current process is written into the lock file: the process id (PID) on the first
+/// line, followed by the ID of the current boot, the start time and the sockets the process
+/// listens on (see [`Owner`]). When a `Lock` is dropped (== goes out-of-scope), the lock file is
+/// removed.
 ///
-/// A `Lock` does never exist without its lock file. Neither does a lock file exist without its
-/// handle, except for one occasion: When the process receives a SIGINT, the destructor will not
-/// run and the lock file remains in the file system while the process is stopped. It is possible
-/// to mitigate this behavior with a SIGINT handler that catches the signal, removes the lock file
-/// and then stops the process. Such a handler can be set up using [`handle_sigint`].
+/// The lock file is additionally locked using `flock`. Since the kernel releases that lock as soon
+/// as the process exits, a lock file left behind by a crashed or killed process is recognized as
+/// stale and taken over. A lock file that is still locked by another process is never taken over.
 ///
 /// A `Lock` is exclusive: It is impossible to clone or create a `Lock` that refers to the same
 /// lock file. The reason is that dropping a `Lock` referencing a lock file that has already been
 /// removed, would cause a runtime error. If you need more than one handle consider using a smart
 /// pointer.
 ///
-/// [`handle_sigint`]: #method.handle_sigint
+/// [`Owner`]: struct.Owner.html
 ///
 /// # Example
 /// ```no_run
 /// # use dynonym::lock::Lock;
 /// let path = "dynonym.lock".into();
-/// let lock = Lock::create(path).unwrap()
-///     .handle_sigint().unwrap();  // optional
+/// let lock = Lock::create(path).unwrap();
 ///
 /// // some code
 ///
-/// lock.release();                 // optional
+/// lock.release();     // optional
 /// ```
 pub struct Lock {
     path: PathBuf,
+    file: File,     // Note: Closing the file releases the flock.
+    owner: Owner,
 }
 
 impl Lock {
     /// Creates a new `Lock`. This includes
     ///
-    /// 1. creating a new lock file at the given path and
-    /// 2. saving the current process id (PID) into the file.
+    /// 1. creating a new lock file at the given path (or opening a stale one),
+    /// 2. locking the file and
+    /// 3. saving information about the current process (incl. the PID) into the file.
     pub fn create(path: PathBuf) -> Result<Self> {
-        // Create a lock file
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&path)
-            .chain_err(|| ErrorKind::LockFileCreate(path.clone()))?;
-
-        // Write the process id into the lock file
-        let pid = format!(
-            "{}\n",
-            unsafe { ::libc::getpid() }
-        );
-        file.write_all(pid.as_bytes())
-            .chain_err(|| ErrorKind::LockFileWrite(path.clone()))?;
-
-        // Create struct
-        let lock = Lock { path };
-        Ok(lock)
-    }
-
-    /// Creates a SIGINT handler for the given `Lock` that makes sure the lock file is removed even
-    /// if the process is interrupted.
-    pub fn handle_sigint(self) -> Result<Self> {
-        // Create a clone
-        //
-        // WARNING: Do not derive Clone for Lock in order to simplify this operation since it is
-        // not a good idea to have a twin in the wild. The relation between a Lock and its lock
-        // file should be 1-to-1 because a lock -- when going out of scope -- removes the lock
-        // file. Having a clone will result in a runtime error as soon as the second lock goes out
-        // of scope and tries to remove the -- no longer existing -- lock file.
-        let twin = Lock { path: self.path.clone() };
-
-        // Setup SIGINT handler
-        // TODO Replace ctrlc by tokio-signal?!
-        ::ctrlc::set_handler(move || {
-            if let Err(err) = twin.release_ref() {
-                handle(&err);
+        loop {
+            // Open the lock file, creating it if necessary
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(&path)
+                .chain_err(|| ErrorKind::LockFileCreate(path.clone()))?;
+
+            // Lock the lock file without waiting
+            let locked = try_flock(&file, libc::LOCK_EX)
+                .chain_err(|| ErrorKind::LockFileLock(path.clone()))?;
+            if !locked {
+                let pid = read(&mut file).ok().and_then(|owner| owner).map(|owner| owner.pid);
+                bail!(ErrorKind::LockFileHeld(path, pid));
+            }
+
+            // Start over if the previous holder removed the lock file in the meantime
+            if !is_same_file(&file, &path) {
+                continue;
             }
-            ::std::process::exit(0);
-        })
-            .chain_err(|| ErrorKind::LockFileSetupSigintHandler)?;
 
-        // Return self
-        Ok(self)
+            // Take over a stale lock file
+            let stale = read(&mut file).chain_err(|| ErrorKind::LockFileRead(path.clone()))?;
+            if let Some(owner) = stale {
+                let staleness = owner.staleness();
+                warn!("Taking over the stale lock file '{}': {}", path.display(), staleness);
+            }
+
+            // Save information about the current process into the lock file
+            let mut lock = Lock { path, file, owner: Owner::current() };
+            lock.write()?;
+            return Ok(lock);
+        }
+    }
+
+    /// Returns information about the process that holds the lock file at the given path. Fails
+    /// unless a `dynonym` process holds the lock file.
+    pub fn holder(path: &Path) -> Result<Owner> {
+        // Open the lock file
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                bail!(ErrorKind::LockFileUnheld(path.into()))
+            },
+            Err(err) => return Err(err).chain_err(|| ErrorKind::LockFileRead(path.into())),
+        };
+
+        // Check whether any process holds the lock file
+        // Note: The lock file is stale if it can be locked.
+        if try_flock(&file, libc::LOCK_SH).chain_err(|| ErrorKind::LockFileLock(path.into()))? {
+            bail!(ErrorKind::LockFileUnheld(path.into()));
+        }
+
+        // Check whether the process is a `dynonym` process
+        let owner = read(&mut file)
+            .chain_err(|| ErrorKind::LockFileRead(path.into()))?
+            .ok_or_else(|| ErrorKind::LockFileRead(path.into()))?;
+        let same = is_same_program(owner.pid)
+            .chain_err(|| ErrorKind::LockFileProgram(path.into(), owner.pid))?;
+        if !same {
+            bail!(ErrorKind::LockFileForeign(path.into(), owner.pid));
+        }
+        Ok(owner)
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Changes the path the lock file is removed from, e.g. after changing the root directory.
+    pub fn relocate(&mut self, path: PathBuf) {
+        self.path = path;
+    }
+
+    /// Saves the sockets the current process listens on (e.g. `127.0.0.1:8053` or
+    /// `unix:/run/dynonym.sock`) into the lock file.
+    pub fn set_sockets(&mut self, sockets: Vec<String>) -> Result<()> {
+        self.owner.sockets = sockets;
+        self.write()
     }
 
     /// Releases the given `Lock` and removes the corresponding lock file.
@@ -105,8 +153,49 @@ impl Lock {
     /// auto-removed as soon as the `Lock` is dropped.
     pub fn release(self) { }
 
+    /// Writes the information about the current process into the lock file.
+    ///
+    /// The lock file is replaced by a new one, so that nobody ever reads it half-written. The new
+    /// lock file is locked before it replaces the old one.
+    fn write(&mut self) -> Result<()> {
+        let content = self.owner.to_string();
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        // Open the temporary file, truncate it if it exists
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)
+            .chain_err(|| ErrorKind::LockFileCreate(tmp.clone()))?;
+
+        // Lock and write the temporary file, then replace the lock file
+        // Note: The temporary file must not be left behind if anything goes wrong.
+        let replaced = try_flock(&file, libc::LOCK_EX)
+            .and_then(|locked| if locked {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::WouldBlock, "Locked by another process"))
+            })
+            .and_then(|_| file.write_all(content.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, &self.path));
+        if let Err(err) = replaced {
+            let _ = remove_file(&tmp);
+            return Err(err).chain_err(|| ErrorKind::LockFileWrite(self.path.clone()));
+        }
+
+        // Note: Closing the old lock file releases its flock, but it is gone already.
+        self.file = file;
+        Ok(())
+    }
+
     fn release_ref(&self) -> Result<()> {
         // Remove the lock file
+        // Note: The file must be removed before it is closed. Otherwise, another process might
+        // lock the file in the meantime, just to see it removed.
         remove_file(&self.path)
             .chain_err(|| ErrorKind::LockFileRemove(self.path.clone()))?;
 
@@ -121,3 +210,203 @@ impl Drop for Lock {
         }
     }
 }
+
+/// Information about the process that holds (or held) a lock file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Owner {
+    /// The process id.
+    pub pid: i32,
+
+    /// The ID of the boot during which the process started, if available.
+    pub boot: Option<String>,
+
+    /// The time the process started (in seconds since the Unix epoch), if available.
+    pub started: Option<u64>,
+
+    /// The sockets the process listens on.
+    pub sockets: Vec<String>,
+}
+
+impl Owner {
+    /// Returns information about the current process.
+    fn current() -> Self {
+        Owner {
+            pid: unsafe { libc::getpid() },
+            boot: boot_id(),
+            started: Some(now()),
+            sockets: Vec::new(),
+        }
+    }
+
+    /// Parses the content of a lock file, i.e. the PID on the first line followed by `key=value`
+    /// lines. Returns nothing if there is no PID, e.g. because the file is empty.
+    fn parse(s: &str) -> Option<Self> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let mut owner = Owner {
+            pid: lines.next()?.parse().ok()?,
+            boot: None,
+            started: None,
+            sockets: Vec::new(),
+        };
+        for line in lines {
+            let mut pair = line.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some("boot"), Some(boot)) => owner.boot = Some(boot.into()),
+                (Some("started"), Some(started)) => owner.started = started.parse().ok(),
+                (Some("socket"), Some(socket)) => owner.sockets.push(socket.into()),
+                _ => (),    // Note: Unknown lines are ignored for the sake of compatibility.
+            }
+        }
+        Some(owner)
+    }
+
+    /// Sends the given signal to the process.
+    pub fn signal(&self, signal: libc::c_int) -> Result<()> {
+        if unsafe { libc::kill(self.pid, signal) } != 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| ErrorKind::ProcessSignal(self.pid, signal));
+        }
+        Ok(())
+    }
+
+    /// Explains why a lock file held by this process is stale.
+    fn staleness(&self) -> String {
+        match (self.boot.as_ref(), boot_id()) {
+            (Some(boot), Some(ref current)) if boot != current => {
+                "It was created during a different boot".into()
+            },
+            _ if !is_alive(self.pid) => format!("Process {} is no longer running", self.pid),
+            _ => format!("Process {} does not lock it anymore", self.pid),
+        }
+    }
+}
+
+impl Display for Owner {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.pid)?;
+        if let Some(ref boot) = self.boot {
+            writeln!(f, "boot={}", boot)?;
+        }
+        if let Some(started) = self.started {
+            writeln!(f, "started={}", started)?;
+        }
+        for socket in &self.sockets {
+            writeln!(f, "socket={}", socket)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the content of a lock file.
+fn read(file: &mut File) -> io::Result<Option<Owner>> {
+    let mut buf = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut buf)?;
+    Ok(Owner::parse(&buf))
+}
+
+/// Tries to lock the given file (exclusively or shared) without waiting. Returns whether the file
+/// has been locked.
+fn try_flock(file: &File, operation: libc::c_int) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.kind() {
+        io::ErrorKind::WouldBlock => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Checks whether the given file is (still) the file at the given path.
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Returns the ID of the current boot, if available.
+fn boot_id() -> Option<String> {
+    let mut buf = String::new();
+    File::open(BOOT_ID).and_then(|mut file| file.read_to_string(&mut buf)).ok()?;
+    Some(buf.trim().into())
+}
+
+/// Checks whether a process with the given PID is running.
+fn is_alive(pid: i32) -> bool {
+    (unsafe { libc::kill(pid, 0) } == 0)
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Checks whether the process with the given PID runs the same program as the current process.
+/// Fails if that cannot be determined, e.g. without `/proc`.
+fn is_same_program(pid: i32) -> io::Result<bool> {
+    let name = |process: &str| -> io::Result<String> {
+        let mut buf = String::new();
+        File::open(format!("/proc/{}/comm", process))?.read_to_string(&mut buf)?;
+        Ok(buf.trim().to_string())
+    };
+    Ok(name(&pid.to_string())? == name("self")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn lock_held() {
+        // Note: The lock file is removed as soon as the lock is dropped.
+        let path = NamedTempFile::new().unwrap().path().to_owned();
+        let mut lock = Lock::create(path.clone()).unwrap();
+        match *Lock::create(path.clone()).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::LockFileHeld(_, Some(pid)) => assert_eq!(pid, unsafe { libc::getpid() }),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+
+        // Note: The replaced lock file is still held.
+        lock.set_sockets(vec!["127.0.0.1:8053".into()]).unwrap();
+        let holder = Lock::holder(&path).unwrap();
+        assert_eq!(holder.pid, unsafe { libc::getpid() });
+        assert_eq!(holder.sockets, lock.owner.sockets);
+        assert!(Lock::create(path.clone()).is_err());
+        assert!(!PathBuf::from(format!("{}.tmp", path.display())).exists());
+    }
+
+    #[test]
+    fn lock_stale() {
+        let path = NamedTempFile::new().unwrap().path().to_owned();
+        File::create(&path).unwrap().write_all(b"2147483647\nboot=foo\n").unwrap();
+        match *Lock::holder(&path).unwrap_err().kind() {
+            ErrorKind::LockFileUnheld(_) => (),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+
+        let lock = Lock::create(path.clone()).unwrap();
+        let mut buf = String::new();
+        File::open(&path).unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(Owner::parse(&buf).unwrap().pid, unsafe { libc::getpid() });
+
+        lock.release();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn owner_parse() {
+        let owner = Owner {
+            pid: 42,
+            boot: Some("foo".into()),
+            started: Some(1514764800),
+            sockets: vec!["127.0.0.1:8053".into(), "unix:/run/dynonym.sock".into()],
+        };
+        assert_eq!(Owner::parse(&owner.to_string()), Some(owner));
+        assert_eq!(Owner::parse("42\n"), Some(Owner {
+            pid: 42,
+            boot: None,
+            started: None,
+            sockets: Vec::new(),
+        }));
+        assert_eq!(Owner::parse(""), None);
+    }
+}
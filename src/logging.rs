@@ -0,0 +1,194 @@
+//! Logging
+//!
+//! `dynonym` (just like Hyper) emits log messages using the [`log`] crate. This module provides
+//! the logger that writes them. The logger is configured by [`config::Log`]: It filters messages
+//! by their level, formats them either as human-readable text or as JSON objects and writes them
+//! to the standard error stream, a file or the local syslog daemon.
+//!
+//! [`log`]: ../../log/index.html
+//! [`config::Log`]: ../config/struct.Log.html
+
+use config::{Log, LogFormat, LogLevel, LogOutput};
+use errors::*;
+
+use chrono::Utc;
+use log::{self, LogLevelFilter, LogMetadata, LogRecord};
+use serde_json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+/// The socket of the local syslog daemon.
+const SYSLOG: &str = "/dev/log";
+
+/// A destination for log messages.
+enum Output {
+    Stderr,
+    File(File),
+    Syslog(UnixDatagram),
+}
+
+/// A log message encoded as JSON.
+#[derive(Serialize)]
+struct Message<'a> {
+    time: &'a str,
+    level: &'a str,
+    target: &'a str,
+    message: &'a str,
+}
+
+/// A logger.
+struct Logger {
+    level: LogLevelFilter,
+    format: LogFormat,
+    output: Mutex<Output>,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &LogRecord) {
+        let level = record.level();
+        if level > self.level {
+            return;
+        }
+
+        // Ignore Hyper's messages unless debugging
+        // Note: Messages of crates using `log` 0.4 lack their location, but not their target.
+        let target = record.target();
+        if target.starts_with("hyper::") && self.level < LogLevelFilter::Debug {
+            return;
+        }
+
+        // Format the message
+        let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let message = record.args().to_string();
+        let line = format(self.format, &time, level, target, &message);
+
+        // Write the message
+        // Note: There is nobody to tell about a failure.
+        let mut output = self.output.lock().unwrap();
+        let _ = match *output {
+            Output::Stderr => writeln!(io::stderr(), "{}", line),
+            Output::File(ref mut file) => writeln!(file, "{}", line),
+            Output::Syslog(ref socket) => {
+                let pid = unsafe { ::libc::getpid() };
+                let line = format!("<{}>{}[{}]: {}", priority(level), crate_name!(), pid, line);
+                socket.send(line.as_bytes()).map(|_| ())
+            },
+        };
+    }
+}
+
+/// Formats a log message.
+fn format(
+    format: LogFormat,
+    time: &str,
+    level: log::LogLevel,
+    target: &str,
+    message: &str,
+) -> String {
+    match format {
+        LogFormat::Text => format!("{} {:<5} {}: {}", time, level, target, message),
+        LogFormat::Json => {
+            let level = level.to_string();
+            let message = Message { time, level: &level, target, message };
+            serde_json::to_string(&message).unwrap()
+        },
+    }
+}
+
+/// Returns the syslog priority (facility "daemon") for the given level.
+fn priority(level: log::LogLevel) -> u8 {
+    let severity = match level {
+        log::LogLevel::Error => 3,
+        log::LogLevel::Warn => 4,
+        log::LogLevel::Info => 6,
+        log::LogLevel::Debug | log::LogLevel::Trace => 7,
+    };
+    3 * 8 + severity
+}
+
+/// Converts a log level into a filter.
+fn filter(level: LogLevel) -> LogLevelFilter {
+    match level {
+        LogLevel::Off => LogLevelFilter::Off,
+        LogLevel::Error => LogLevelFilter::Error,
+        LogLevel::Warn => LogLevelFilter::Warn,
+        LogLevel::Info => LogLevelFilter::Info,
+        LogLevel::Debug => LogLevelFilter::Debug,
+        LogLevel::Trace => LogLevelFilter::Trace,
+    }
+}
+
+/// Sets up the logger given the configuration.
+///
+/// This function may only be called once per process.
+pub fn init(config: &Log) -> Result<()> {
+    // Open the output
+    let output = match config.output {
+        LogOutput::Stderr => Output::Stderr,
+        LogOutput::File => {
+            let path = config.file.as_ref().ok_or(ErrorKind::LogFileMissing)?;
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .chain_err(|| ErrorKind::LogFileOpen(path.clone()))?;
+            Output::File(file)
+        },
+        LogOutput::Syslog => {
+            let socket = UnixDatagram::unbound()
+                .and_then(|socket| socket.connect(SYSLOG).map(|_| socket))
+                .chain_err(|| ErrorKind::LogSyslogConnect(SYSLOG.into()))?;
+            Output::Syslog(socket)
+        },
+    };
+
+    // Set up the logger
+    let level = filter(config.level);
+    log::set_logger(|max_level| {
+        max_level.set(level);
+        Box::new(Logger {
+            level,
+            format: config.format,
+            output: Mutex::new(output),
+        })
+    })
+        .chain_err(|| ErrorKind::LogInit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_text() {
+        let line = format(
+            LogFormat::Text,
+            "2018-01-01T00:00:00.000Z",
+            log::LogLevel::Warn,
+            "dynonym::dns",
+            "foo",
+        );
+        assert_eq!(line, "2018-01-01T00:00:00.000Z WARN  dynonym::dns: foo");
+    }
+
+    #[test]
+    fn format_json() {
+        let line = format(LogFormat::Json, "now", log::LogLevel::Info, "hyper", "\"foo\"");
+        assert_eq!(
+            line,
+            r#"{"time":"now","level":"INFO","target":"hyper","message":"\"foo\""}"#
+        );
+    }
+
+    #[test]
+    fn priority_daemon() {
+        assert_eq!(priority(log::LogLevel::Error), 27);
+        assert_eq!(priority(log::LogLevel::Trace), 31);
+    }
+}
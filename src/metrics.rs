@@ -0,0 +1,292 @@
+//! Metrics
+//!
+//! A [`Registry`] collects counters, gauges and histograms about the server's work: update
+//! requests, failed logins, DNS UPDATE messages and SOA queries. The HTTP server exports them at
+//! `/metrics` using the Prometheus text format.
+//!
+//! [`Registry`]: struct.Registry.html
+
+use history::Effect;
+use types::{Domain, Rcode};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds (in seconds) of the buckets of a latency histogram.
+const BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The kind of a metric.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+/// A metric: its name, kind and description.
+type Family = (&'static str, Kind, &'static str);
+
+const UPDATES: Family = (
+    "dynonym_updates_total",
+    Kind::Counter,
+    "Updated records by result; \"unchanged\" means no update was necessary",
+);
+const AUTH_FAILURES: Family = (
+    "dynonym_auth_failures_total",
+    Kind::Counter,
+    "Failed logins by method",
+);
+const DNS_UPDATE_DURATION: Family = (
+    "dynonym_dns_update_duration_seconds",
+    Kind::Histogram,
+    "Time until the DNS server responded to an UPDATE message, by zone",
+);
+const DNS_RESPONSES: Family = (
+    "dynonym_dns_responses_total",
+    Kind::Counter,
+    "Responses of the DNS server to UPDATE messages by zone and response code",
+);
+const SOA_QUERIES: Family = (
+    "dynonym_soa_queries_total",
+    Kind::Counter,
+    "SOA queries sent in order to find the zone of a domain",
+);
+const LAST_UPDATE: Family = (
+    "dynonym_last_update_timestamp_seconds",
+    Kind::Gauge,
+    "Time of the last successful update by domain",
+);
+
+/// All metrics in the order they are exported.
+const FAMILIES: &[Family] =
+    &[UPDATES, AUTH_FAILURES, DNS_UPDATE_DURATION, DNS_RESPONSES, SOA_QUERIES, LAST_UPDATE];
+
+/// The labels of a time series, i.e. pairs of names and values.
+type Labels = Vec<(&'static str, String)>;
+
+/// A histogram: the number of observations per bucket, their sum and count.
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// A collection of metrics.
+///
+/// A `Registry` is shared between all threads. Its `Display` implementation renders the metrics
+/// using the Prometheus text format (version 0.0.4).
+#[derive(Debug)]
+pub struct Registry {
+    values: Mutex<BTreeMap<(&'static str, Labels), f64>>,
+    histograms: Mutex<BTreeMap<(&'static str, Labels), Histogram>>,
+}
+
+impl Registry {
+    /// Creates a new registry.
+    pub fn new() -> Self {
+        // A counter without labels starts at zero
+        let mut values = BTreeMap::new();
+        values.insert((SOA_QUERIES.0, vec![]), 0.0);
+
+        Registry {
+            values: Mutex::new(values),
+            histograms: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts an updated record given the result.
+    pub fn update(&self, result: Effect) {
+        let result = match result {
+            Effect::Changed => "changed",
+            Effect::Unchanged => "unchanged",
+            Effect::Failed => "failed",
+            Effect::Rejected => "rejected",
+        };
+        self.add(UPDATES, vec![("result", result.into())], 1.0);
+    }
+
+    /// Counts a failed login given the method, e.g. `password` or `signature`.
+    pub fn auth_failure(&self, method: &'static str) {
+        self.add(AUTH_FAILURES, vec![("method", method.into())], 1.0);
+    }
+
+    /// Observes an UPDATE message sent to the DNS server given the zone, the time until the
+    /// response arrived and the response code, if there was a response.
+    pub fn dns_update<Z: Display>(&self, zone: Z, duration: Duration, rcode: Option<Rcode>) {
+        let zone = zone.to_string();
+        let secs = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9;
+        self.histograms.lock().unwrap()
+            .entry((DNS_UPDATE_DURATION.0, vec![("zone", zone.clone())]))
+            .or_insert_with(Histogram::default)
+            .observe(secs);
+        if let Some(rcode) = rcode {
+            self.add(DNS_RESPONSES, vec![("zone", zone), ("rcode", rcode.to_string())], 1.0);
+        }
+    }
+
+    /// Counts an SOA query.
+    pub fn soa_query(&self) {
+        self.add(SOA_QUERIES, vec![], 1.0);
+    }
+
+    /// Remembers the time (in seconds since the Unix epoch) of a successful update.
+    pub fn last_update(&self, domain: &Domain, time: u64) {
+        let key = (LAST_UPDATE.0, vec![("domain", domain.to_string())]);
+        self.values.lock().unwrap().insert(key, time as f64);
+    }
+
+    /// Adds the given value to a counter.
+    fn add(&self, family: Family, labels: Labels, value: f64) {
+        *self.values.lock().unwrap().entry((family.0, labels)).or_insert(0.0) += value;
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for Registry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let values = self.values.lock().unwrap();
+        let histograms = self.histograms.lock().unwrap();
+
+        for &(name, kind, help) in FAMILIES {
+            let kind_str = match kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+                Kind::Histogram => "histogram",
+            };
+            writeln!(f, "# HELP {} {}", name, help)?;
+            writeln!(f, "# TYPE {} {}", name, kind_str)?;
+
+            for (&(series, ref labels), histogram) in histograms.iter() {
+                if series == name {
+                    render_histogram(f, name, labels, histogram)?;
+                }
+            }
+            for (&(series, ref labels), value) in values.iter() {
+                if series == name {
+                    writeln!(f, "{}{} {}", name, render_labels(labels, None), value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the time series of a histogram.
+fn render_histogram(
+    f: &mut Formatter,
+    name: &str,
+    labels: &Labels,
+    histogram: &Histogram,
+) -> fmt::Result {
+    for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+        let le = render_labels(labels, Some(&bound.to_string()));
+        writeln!(f, "{}_bucket{} {}", name, le, count)?;
+    }
+    let le = render_labels(labels, Some("+Inf"));
+    writeln!(f, "{}_bucket{} {}", name, le, histogram.count)?;
+    writeln!(f, "{}_sum{} {}", name, render_labels(labels, None), histogram.sum)?;
+    writeln!(f, "{}_count{} {}", name, render_labels(labels, None), histogram.count)
+}
+
+/// Renders labels, optionally including the upper bound of a histogram bucket.
+fn render_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<_> = labels.iter().map(|&(name, ref value)| (name, &value[..])).collect();
+    if let Some(le) = le {
+        pairs.push(("le", le));
+    }
+    if pairs.is_empty() {
+        return String::new();
+    }
+
+    let mut s = String::from("{");
+    for (i, &(name, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        write!(s, "{}=\"", name).unwrap();
+        for c in value.chars() {
+            match c {
+                '\\' => s.push_str("\\\\"),
+                '"' => s.push_str("\\\""),
+                '\n' => s.push_str("\\n"),
+                c => s.push(c),
+            }
+        }
+        s.push('"');
+    }
+    s.push('}');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_render() {
+        let registry = Registry::new();
+        registry.update(Effect::Changed);
+        registry.update(Effect::Changed);
+        registry.update(Effect::Unchanged);
+        registry.auth_failure("password");
+        registry.last_update(&"example.org".parse().unwrap(), 1514764800);
+
+        let text = registry.to_string();
+        assert!(text.contains("# TYPE dynonym_updates_total counter\n"));
+        assert!(text.contains("dynonym_updates_total{result=\"changed\"} 2\n"));
+        assert!(text.contains("dynonym_updates_total{result=\"unchanged\"} 1\n"));
+        assert!(text.contains("dynonym_auth_failures_total{method=\"password\"} 1\n"));
+        assert!(text.contains("dynonym_soa_queries_total 0\n"));
+        assert!(text.contains(
+            "dynonym_last_update_timestamp_seconds{domain=\"example.org\"} 1514764800\n"
+        ));
+    }
+
+    #[test]
+    fn registry_histogram() {
+        let registry = Registry::new();
+        registry.dns_update("example.org", Duration::from_millis(20), Some(Rcode::NOERROR));
+        registry.dns_update("example.org", Duration::from_secs(20), None);
+
+        let text = registry.to_string();
+        let series = |suffix: &str, labels: &str, value: u64| {
+            format!("dynonym_dns_update_duration_seconds_{}{{{}}} {}\n", suffix, labels, value)
+        };
+        assert!(text.contains(&series("bucket", "zone=\"example.org\",le=\"0.01\"", 0)));
+        assert!(text.contains(&series("bucket", "zone=\"example.org\",le=\"0.025\"", 1)));
+        assert!(text.contains(&series("bucket", "zone=\"example.org\",le=\"+Inf\"", 2)));
+        assert!(text.contains(&series("count", "zone=\"example.org\"", 2)));
+        assert!(text.contains(
+            "dynonym_dns_responses_total{zone=\"example.org\",rcode=\"NOERROR\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn labels_escaped() {
+        let labels = vec![("reason", "a \"b\"\\\n".to_string())];
+        assert_eq!(render_labels(&labels, None), "{reason=\"a \\\"b\\\"\\\\\\n\"}");
+    }
+}
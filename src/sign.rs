@@ -0,0 +1,158 @@
+//! Pre-signed, expiring update URLs
+//!
+//! A pre-signed update URL allows its holder to update exactly one domain on behalf of a user
+//! without knowing the user's password. The URL contains the user's name, the domain, optionally
+//! fixed IP addresses, an expiry time and an HMAC-SHA256 signature over all of these parameters.
+//! The signature is created with the user's [`Secret`]. Any change to a parameter invalidates the
+//! signature.
+//!
+//! If neither an IPv4 nor an IPv6 address is fixed, the holder updates the domain with the address
+//! the request originates from.
+//!
+//! [`Secret`]: ../types/struct.Secret.html
+//!
+//! # Example
+//!
+//! ```
+//! # use dynonym::sign::{now, Signed};
+//! # use dynonym::types::Secret;
+//! let secret = Secret::generate().unwrap();
+//! let signed = Signed {
+//!     user: "tobias".into(),
+//!     domain: "example.org".parse().unwrap(),
+//!     ipv4: None,
+//!     ipv6: None,
+//!     expires: now() + 3600 /*sec*/,
+//! };
+//!
+//! let sig = signed.sign(&secret);
+//! assert!(signed.verify(&secret, &sig));
+//! assert!(!signed.is_expired());
+//! ```
+
+use types::{Domain, Secret};
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+/// The signed parameters of an update URL.
+#[derive(Debug)]
+pub struct Signed {
+    /// The user the update is performed for.
+    pub user: String,
+
+    /// The domain that may be updated.
+    pub domain: Domain,
+
+    /// The fixed IPv4 address, if any.
+    pub ipv4: Option<Ipv4Addr>,
+
+    /// The fixed IPv6 address, if any.
+    pub ipv6: Option<Ipv6Addr>,
+
+    /// The expiry time in seconds since the UNIX epoch.
+    pub expires: u64,
+}
+
+impl Signed {
+    /// Returns the canonical message that is signed.
+    pub fn message(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.user,
+            self.domain,
+            self.ipv4.map(|ip| ip.to_string()).unwrap_or_default(),
+            self.ipv6.map(|ip| ip.to_string()).unwrap_or_default(),
+            self.expires,
+        )
+    }
+
+    /// Signs the parameters with the given secret.
+    pub fn sign(&self, secret: &Secret) -> String {
+        secret.sign(&self.message())
+    }
+
+    /// Verifies whether the given signature was created for the parameters with the given secret.
+    ///
+    /// This method does not check the expiry time. Use [`is_expired`] to do so!
+    ///
+    /// [`is_expired`]: #method.is_expired
+    pub fn verify(&self, secret: &Secret, sig: &str) -> bool {
+        secret.verify(&self.message(), sig)
+    }
+
+    /// Checks whether the expiry time has passed.
+    pub fn is_expired(&self) -> bool {
+        now() > self.expires
+    }
+
+    /// Assembles a complete update URL given the base URL of the server, e.g.
+    /// `http://127.0.0.1:8053`.
+    pub fn url(&self, base: &str, secret: &Secret) -> String {
+        let mut url = format!(
+            "{}/dns/update?domain={}&user={}&expires={}",
+            base.trim_right_matches('/'),
+            utf8_percent_encode(&self.domain.0, PATH_SEGMENT_ENCODE_SET),
+            utf8_percent_encode(&self.user, PATH_SEGMENT_ENCODE_SET),
+            self.expires,
+        );
+        if let Some(ipv4) = self.ipv4 {
+            url.push_str(&format!("&ipv4={}", ipv4));
+        }
+        if let Some(ipv6) = self.ipv6 {
+            url.push_str(&format!("&ipv6={}", ipv6));
+        }
+        url.push_str(&format!("&sig={}", self.sign(secret)));
+        url
+    }
+}
+
+/// Returns the current time in seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed() -> Signed {
+        Signed {
+            user: "tobias".into(),
+            domain: "example.org".parse().unwrap(),
+            ipv4: Some("127.0.0.1".parse().unwrap()),
+            ipv6: None,
+            expires: now() + 60,
+        }
+    }
+
+    #[test]
+    fn signed_verify() {
+        let secret = Secret::generate().unwrap();
+        let signed = signed();
+        let sig = signed.sign(&secret);
+        assert!(signed.verify(&secret, &sig));
+        assert!(!signed.verify(&Secret::generate().unwrap(), &sig));
+    }
+
+    #[test]
+    fn signed_tampered() {
+        let secret = Secret::generate().unwrap();
+        let mut signed = signed();
+        let sig = signed.sign(&secret);
+        signed.ipv4 = Some("127.0.0.2".parse().unwrap());
+        assert!(!signed.verify(&secret, &sig));
+    }
+
+    #[test]
+    fn signed_expired() {
+        let mut signed = signed();
+        assert!(!signed.is_expired());
+        signed.expires = now() - 1;
+        assert!(signed.is_expired());
+    }
+}
@@ -7,11 +7,17 @@
 //!
 //! [std]: https://doc.rust-lang.org/std/
 
-use rocket::http::RawStr;
-use rocket::request::FromFormValue;
-use std::convert::TryInto;
+use errors::{Error, ErrorKind, ResultExt};
+
+use base64::{decode_config, encode_config, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+use sha2::Sha256;
 use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
 use std::str::FromStr;
+use trust_dns::op::ResponseCode;
 use trust_dns::rr::domain::Name;
 use trust_dns_proto::error::ProtoError;
 
@@ -38,36 +44,249 @@ impl FromStr for Domain {
     }
 }
 
-impl<'v> FromFormValue<'v> for Domain {
-    type Error = &'v RawStr;
+impl Domain {
+    /// Converts the domain name into the Trust DNS format.
+    pub fn to_name(&self) -> Result<Name, ProtoError> {
+        Name::parse(&self.0, None)
+    }
+}
 
-    fn from_form_value(v: &'v RawStr) -> Result<Self, Self::Error> {
-        let inner = String::from_form_value(v)?;
-        Ok(Domain(inner))
+/// A block of IP addresses in CIDR notation.
+///
+/// A `Cidr` represents a range of IPv4 or IPv6 addresses sharing a common prefix, e.g.
+/// `192.0.2.0/24` or `2001:db8::/32`. A single IP address without a prefix length is a block of
+/// exactly one address.
+///
+/// An IPv4 block also contains the IPv4-mapped IPv6 representations of its addresses (e.g.
+/// `::ffff:192.0.2.1`), since a server listening on an IPv6 socket sees IPv4 clients that way.
+///
+/// # Example
+///
+/// ```
+/// use dynonym::types::Cidr;
+///
+/// let cidr: Cidr = "192.0.2.0/24".parse().unwrap();
+/// assert!( cidr.contains("192.0.2.1".parse().unwrap()));
+/// assert!(!cidr.contains("198.51.100.1".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Cidr {
+    /// Checks whether the given IP address is part of the block.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        use std::net::IpAddr::{V4, V6};
+        match (self.addr, ip) {
+            (V4(net), V4(ip)) => prefix_eq(&net.octets(), &ip.octets(), self.len),
+            (V6(net), V6(ip)) => prefix_eq(&net.octets(), &ip.octets(), self.len),
+            (V4(net), V6(ip)) => {
+                let octets = ip.octets();
+                octets[..10].iter().all(|&x| x == 0) && octets[10..12] == [0xff, 0xff]
+                    && prefix_eq(&net.octets(), &octets[12..], self.len)
+            },
+            (V6(_), V4(_)) => false,
+        }
     }
 }
 
-impl TryInto<Name> for Domain {
-    type Error = ProtoError;
-    fn try_into(self) -> Result<Name, Self::Error> {
-        Name::parse(&self.0, None)
+/// Compares the first `len` bits of two byte slices.
+fn prefix_eq(a: &[u8], b: &[u8], len: u8) -> bool {
+    let (bytes, bits) = ((len / 8) as usize, len % 8);
+    a[..bytes] == b[..bytes] && (bits == 0 || (a[bytes] ^ b[bytes]) >> (8 - bits) == 0)
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap().parse()
+            .chain_err(|| ErrorKind::CidrParse(s.into()))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let len = match parts.next() {
+            Some(len) => len.parse().chain_err(|| ErrorKind::CidrParse(s.into()))?,
+            None => max,
+        };
+        if len > max {
+            bail!(ErrorKind::CidrParse(s.into()));
+        }
+        Ok(Cidr { addr, len })
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|err: Error| D::Error::custom(err.to_string()))
+    }
+}
+
+/// A DNS response code (RCODE).
+///
+/// An `Rcode` represents the result of a DNS request as registered with the IANA, e.g. `NOERROR`
+/// (0) or `REFUSED` (5). It is displayed, parsed and serialized using its mnemonic.
+///
+/// # Example
+///
+/// ```
+/// use dynonym::types::Rcode;
+///
+/// assert_eq!(Rcode::REFUSED.to_string(), "REFUSED");
+/// assert_eq!(Rcode(42).to_string(), "RCODE42");
+/// assert_eq!("NOTAUTH".parse::<Rcode>().unwrap(), Rcode::NOTAUTH);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Rcode(pub u16);
+
+impl Rcode {
+    /// No error.
+    pub const NOERROR: Rcode = Rcode(0);
+
+    /// Server failure.
+    pub const SERVFAIL: Rcode = Rcode(2);
+
+    /// Query refused.
+    pub const REFUSED: Rcode = Rcode(5);
+
+    /// Server not authoritative for the zone, or not authorized.
+    pub const NOTAUTH: Rcode = Rcode(9);
+
+    /// Name not contained in the zone.
+    pub const NOTZONE: Rcode = Rcode(10);
+}
+
+/// The mnemonics of the response codes.
+const MNEMONICS: &[(u16, &str)] = &[
+    (0, "NOERROR"),
+    (1, "FORMERR"),
+    (2, "SERVFAIL"),
+    (3, "NXDOMAIN"),
+    (4, "NOTIMP"),
+    (5, "REFUSED"),
+    (6, "YXDOMAIN"),
+    (7, "YXRRSET"),
+    (8, "NXRRSET"),
+    (9, "NOTAUTH"),
+    (10, "NOTZONE"),
+    (16, "BADSIG"),
+    (17, "BADKEY"),
+    (18, "BADTIME"),
+    (19, "BADMODE"),
+    (20, "BADNAME"),
+    (21, "BADALG"),
+    (22, "BADTRUNC"),
+    (23, "BADCOOKIE"),
+];
+
+impl Display for Rcode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match MNEMONICS.iter().find(|&&(code, _)| code == self.0) {
+            Some(&(_, mnemonic)) => f.write_str(mnemonic),
+            None => write!(f, "RCODE{}", self.0),
+        }
+    }
+}
+
+impl FromStr for Rcode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(&(code, _)) = MNEMONICS.iter().find(|&&(_, mnemonic)| mnemonic == s) {
+            return Ok(Rcode(code));
+        }
+        if s.starts_with("RCODE") {
+            let code = s[5..].parse().chain_err(|| ErrorKind::RcodeParse(s.into()))?;
+            return Ok(Rcode(code));
+        }
+        bail!(ErrorKind::RcodeParse(s.into()))
+    }
+}
+
+impl From<ResponseCode> for Rcode {
+    fn from(rcode: ResponseCode) -> Self {
+        Rcode(rcode.into())
+    }
+}
+
+impl Serialize for Rcode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rcode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|err: Error| D::Error::custom(err.to_string()))
+    }
+}
+
+/// A password hashing algorithm including its parameters.
+///
+/// The default is Argon2id with the minimum parameters recommended by OWASP.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum Algorithm {
+    /// The bcrypt algorithm.
+    Bcrypt {
+        /// The cost factor, i.e. the binary logarithm of the number of rounds.
+        cost: u32,
+    },
+
+    /// The Argon2id algorithm (RFC 9106).
+    Argon2id {
+        /// The amount of memory (in KiB).
+        memory: u32,
+
+        /// The number of iterations.
+        iterations: u32,
+
+        /// The degree of parallelism.
+        lanes: u32,
+    },
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::Argon2id {
+            memory: 19456 /*KiB*/,
+            iterations: 2,
+            lanes: 1,
+        }
     }
 }
 
 /// A salted and cryptographically hashed string.
 ///
-/// A `Hash` represents a string that was salted and cryptographically hashed using the bcrypt
-/// algorithm. The salt is stored alongside the hash. A `Hash` is well suited to store encrypted
-/// passwords.
+/// A `Hash` represents a string that was salted and cryptographically hashed using either the
+/// Argon2id or the bcrypt algorithm. The salt and the algorithm's parameters are stored alongside
+/// the hash using the PHC string format (Argon2id) or the modular crypt format (bcrypt). A `Hash`
+/// is well suited to store encrypted passwords.
 ///
-/// Since `Hash` implements `From<&str>`, the preferred method to obtain a `Hash` is to convert a
-/// string slice using `Into<Hash>` as shown in the example below.
+/// Since `Hash` implements `From<&str>`, the preferred method to obtain a `Hash` with the default
+/// [`Algorithm`] is to convert a string slice using `Into<Hash>` as shown in the example below.
+/// Use [`new`] to choose another algorithm.
 ///
 /// A `Hash` can be compared to a given string slice (== verified) with the method [`is`].
 ///
 /// Because of different, randomly chosen salts, two hashes are (almost) never equal, even if
 /// obtained from the exact same plain text.
 ///
+/// [`Algorithm`]: enum.Algorithm.html
+/// [`new`]: #method.new
 /// [`is`]: #method.is
 ///
 /// # Example
@@ -82,22 +301,177 @@ impl TryInto<Name> for Domain {
 /// assert!( h.is("foo"));
 /// assert!(!h.is("bar"));
 /// ```
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Hash(String);
 
 impl Hash {
+    /// Hashes the given string slice using the given algorithm.
+    pub fn new(plain: &str, algorithm: &Algorithm) -> Result<Self, Error> {
+        let hash = match *algorithm {
+            Algorithm::Bcrypt { cost } => {
+                ::bcrypt::hash(plain, cost).map_err(|err| err.to_string())
+            },
+            Algorithm::Argon2id { memory, iterations, lanes } => {
+                use argon2::{hash_encoded, Config, Variant};
+                use rand::{OsRng, Rng};
+
+                let mut salt = [0u8; 16];
+                OsRng::new()
+                    .map_err(|err| ErrorKind::PasswordHash(err.to_string()))?
+                    .fill_bytes(&mut salt);
+                let config = Config {
+                    variant: Variant::Argon2id,
+                    mem_cost: memory,
+                    time_cost: iterations,
+                    lanes,
+                    ..Config::default()
+                };
+                hash_encoded(plain.as_bytes(), &salt, &config).map_err(|err| err.to_string())
+            },
+        };
+        Ok(Hash(hash.map_err(ErrorKind::PasswordHash)?))
+    }
+
     /// Verifies whether `self` is a hashed version of the given string slice.
+    ///
+    /// A malformed hash never matches. In that case, an error is logged.
     pub fn is(&self, plain: &str) -> bool {
-        use bcrypt::verify;
-        verify(plain, &self.0).unwrap()
+        let result = match self.scheme() {
+            Some("2a") | Some("2b") | Some("2y") if !self.is_valid_bcrypt() =>
+                Err("malformed bcrypt hash".into()),
+            Some("2a") | Some("2b") | Some("2y") => ::bcrypt::verify(plain, &self.0)
+                .map_err(|err| err.to_string()),
+            Some("argon2i") | Some("argon2d") | Some("argon2id") =>
+                ::argon2::verify_encoded(&self.0, plain.as_bytes())
+                    .map_err(|err| err.to_string()),
+            _ => Err("unknown algorithm".into()),
+        };
+        result.unwrap_or_else(|err| {
+            error!("Cannot verify malformed password hash: {}", err);
+            false
+        })
+    }
+
+    /// Checks whether `self` was created with an algorithm or parameters other than the given
+    /// ones, i.e. whether the plain text should be hashed again.
+    pub fn needs_rehash(&self, algorithm: &Algorithm) -> bool {
+        self.algorithm() != Some(*algorithm)
+    }
+
+    /// Checks whether `self` is a well-formed bcrypt hash. (The bcrypt crate panics otherwise.)
+    fn is_valid_bcrypt(&self) -> bool {
+        let fields: Vec<&str> = self.0.split('$').collect();
+        fields.len() == 4
+            && fields[2].len() == 2
+            && fields[2].parse().map(|cost: u32| cost >= 4 && cost <= 31).unwrap_or(false)
+            && fields[3].len() == 53
+            && fields[3].chars().all(|c| c == '.' || c == '/' || c.is_ascii_alphanumeric())
+    }
+
+    /// Returns the identifier of the algorithm, e.g. `2y` or `argon2id`.
+    fn scheme(&self) -> Option<&str> {
+        let mut fields = self.0.split('$');
+        match fields.next() {
+            Some("") => fields.next(),
+            _ => None,
+        }
+    }
+
+    /// Returns the algorithm including its parameters, if it is known.
+    fn algorithm(&self) -> Option<Algorithm> {
+        // Fields: "", scheme, (version,) parameters, salt and hash
+        let fields: Vec<&str> = self.0.split('$').collect();
+        match self.scheme()? {
+            "2a" | "2b" | "2y" => Some(Algorithm::Bcrypt {
+                cost: fields.get(2)?.parse().ok()?,
+            }),
+            "argon2id" => {
+                let (mut memory, mut iterations, mut lanes) = (None, None, None);
+                for param in fields.get(3)?.split(',') {
+                    let mut kv = param.splitn(2, '=');
+                    let (key, value) = (kv.next()?, kv.next()?.parse().ok()?);
+                    match key {
+                        "m" => memory = Some(value),
+                        "t" => iterations = Some(value),
+                        "p" => lanes = Some(value),
+                        _ => return None,
+                    }
+                }
+                Some(Algorithm::Argon2id {
+                    memory: memory?,
+                    iterations: iterations?,
+                    lanes: lanes?,
+                })
+            },
+            _ => None,
+        }
     }
 }
 
 impl<'a> From<&'a str> for Hash {
+    /// Hashes the given string slice using the default algorithm.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system does not provide any randomness for the salt.
     fn from(plain: &'a str) -> Self {
-        use bcrypt::{hash, DEFAULT_COST};
-        let hash = hash(plain, DEFAULT_COST).unwrap();
-        Hash(hash)
+        Hash::new(plain, &Algorithm::default()).expect("Cannot hash with the default algorithm")
+    }
+}
+
+/// A randomly generated secret key.
+///
+/// A `Secret` represents a key that is only known to `dynonym`. It is used to sign messages with
+/// HMAC-SHA256 and to verify such signatures later on, e.g. when handing out pre-signed update
+/// URLs. The key is stored URL-safe base64-encoded.
+///
+/// # Example
+///
+/// ```
+/// use dynonym::types::Secret;
+///
+/// // Create
+/// let s = Secret::generate().unwrap();
+///
+/// // Sign and verify
+/// let sig = s.sign("foo");
+/// assert!( s.verify("foo", &sig));
+/// assert!(!s.verify("bar", &sig));
+/// ```
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Generates a new secret using the operating system's random number generator.
+    pub fn generate() -> Result<Self, Error> {
+        use rand::{OsRng, Rng};
+        let mut key = [0u8; 32];
+        OsRng::new()
+            .map_err(|err| ErrorKind::SecretGenerate(err.to_string()))?
+            .fill_bytes(&mut key);
+        Ok(Secret(encode_config(&key, URL_SAFE_NO_PAD)))
+    }
+
+    /// Signs the given message and returns the URL-safe base64-encoded signature.
+    pub fn sign(&self, msg: &str) -> String {
+        encode_config(&self.mac(msg).finalize().into_bytes(), URL_SAFE_NO_PAD)
+    }
+
+    /// Verifies whether the given signature was created for the given message using `self`.
+    ///
+    /// The comparison runs in constant time.
+    pub fn verify(&self, msg: &str, sig: &str) -> bool {
+        match decode_config(sig, URL_SAFE_NO_PAD) {
+            Ok(sig) => self.mac(msg).verify_slice(&sig).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn mac(&self, msg: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(msg.as_bytes());
+        mac
     }
 }
 
@@ -123,4 +497,103 @@ mod tests {
         let h2: Hash = "foo".into();
         assert!(h1 != h2);  // different salts!
     }
+
+    #[test]
+    fn hash_bcrypt() {
+        let bcrypt = Algorithm::Bcrypt { cost: 4 };
+        let h = Hash::new("foo", &bcrypt).unwrap();
+        assert!(h.is("foo"));
+        assert!(!h.is("bar"));
+        assert!(!h.needs_rehash(&bcrypt));
+        assert!(h.needs_rehash(&Algorithm::Bcrypt { cost: 5 }));
+        assert!(h.needs_rehash(&Algorithm::default()));
+    }
+
+    #[test]
+    fn hash_argon2id() {
+        let argon2id = Algorithm::Argon2id { memory: 64, iterations: 1, lanes: 1 };
+        let h = Hash::new("foo", &argon2id).unwrap();
+        assert!(h.is("foo"));
+        assert!(!h.is("bar"));
+        assert!(!h.needs_rehash(&argon2id));
+        assert!(h.needs_rehash(&Algorithm::Argon2id { memory: 64, iterations: 2, lanes: 1 }));
+        assert!(h.needs_rehash(&Algorithm::Bcrypt { cost: 4 }));
+    }
+
+    #[test]
+    fn hash_malformed() {
+        let malformed = [
+            "",
+            "foo",
+            "$2y$",
+            "$2y$04$short",
+            "$2y$04$!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!",
+            "$argon2id$v=19$m=x",
+            "$md5$foo",
+        ];
+        for malformed in &malformed {
+            let h = Hash(malformed.to_string());
+            assert!(!h.is("foo"));
+            assert!(h.needs_rehash(&Algorithm::default()));
+        }
+    }
+
+    #[test]
+    fn cidr_v4() {
+        let cidr: Cidr = "192.0.2.0/23".parse().unwrap();
+        assert!(cidr.contains("192.0.2.0".parse().unwrap()));
+        assert!(cidr.contains("192.0.3.255".parse().unwrap()));
+        assert!(cidr.contains("::ffff:192.0.3.1".parse().unwrap()));
+        assert!(!cidr.contains("192.0.4.0".parse().unwrap()));
+        assert!(!cidr.contains("2001:db8::".parse().unwrap()));
+    }
+
+    #[test]
+    fn cidr_v6() {
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains("2001:db8:ffff::1".parse().unwrap()));
+        assert!(!cidr.contains("2001:db9::1".parse().unwrap()));
+        assert!(!cidr.contains("192.0.2.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn cidr_single() {
+        let cidr: Cidr = "192.0.2.1".parse().unwrap();
+        assert_eq!(cidr.to_string(), "192.0.2.1/32");
+        assert!(cidr.contains("192.0.2.1".parse().unwrap()));
+        assert!(!cidr.contains("192.0.2.2".parse().unwrap()));
+    }
+
+    #[test]
+    fn cidr_invalid() {
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/x".parse::<Cidr>().is_err());
+        assert!("example.org".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn rcode_roundtrip() {
+        for rcode in &[Rcode::NOERROR, Rcode::REFUSED, Rcode(11), Rcode(4095)] {
+            assert_eq!(rcode.to_string().parse::<Rcode>().unwrap(), *rcode);
+        }
+        assert!("RCODEX".parse::<Rcode>().is_err());
+        assert!("refused".parse::<Rcode>().is_err());
+    }
+
+    #[test]
+    fn secret_sign_verify() {
+        let s = Secret::generate().unwrap();
+        let sig = s.sign("foo");
+        assert!(s.verify("foo", &sig));
+        assert!(!s.verify("bar", &sig));
+        assert!(!s.verify("foo", "not a signature"));
+    }
+
+    #[test]
+    fn secret_random() {
+        let s1 = Secret::generate().unwrap();
+        let s2 = Secret::generate().unwrap();
+        assert!(s1 != s2);
+        assert!(!s2.verify("foo", &s1.sign("foo")));
+    }
 }